@@ -0,0 +1,92 @@
+use super::pipeline::{ExrPrecision, Pipeline, PixelFilter, Scene, StopCriteria};
+use wgpu::{
+    Backends, CommandEncoderDescriptor, Device, Instance, InstanceDescriptor, Maintain,
+    PowerPreference, Queue, RequestAdapterOptions, TextureFormat,
+};
+use winit::dpi::PhysicalSize;
+
+/// Renders without a window, for batch renders.
+pub struct Headless {
+    device: Device,
+    queue: Queue,
+    pipeline: Pipeline,
+}
+
+impl Headless {
+    pub async fn new(size: PhysicalSize<u32>, scene: Scene) -> Self {
+        let instance = Instance::new(InstanceDescriptor {
+            backends: Backends::all(),
+            dx12_shader_compiler: Default::default(),
+        });
+
+        let adapter = instance
+            .request_adapter(&RequestAdapterOptions {
+                power_preference: PowerPreference::HighPerformance,
+                compatible_surface: None,
+                force_fallback_adapter: false,
+            })
+            .await
+            .unwrap();
+
+        let (device, queue) = super::request_device(&adapter).await;
+
+        // Nothing is displayed, so the format only matters for screenshots.
+        let pipeline = Pipeline::new(&device, &queue, TextureFormat::Rgba8UnormSrgb, size, scene);
+        pipeline.put_random_texture(&queue);
+
+        Headless {
+            device,
+            queue,
+            pipeline,
+        }
+    }
+
+    pub fn set_denoiser_enabled(&mut self, enabled: bool) {
+        self.pipeline.set_denoiser_enabled(enabled);
+    }
+
+    pub fn set_adaptive_sampling(&mut self, enabled: bool) {
+        self.pipeline.set_adaptive_sampling(&self.queue, enabled);
+    }
+
+    pub fn set_noise_threshold(&mut self, threshold: f32) {
+        self.pipeline.set_noise_threshold(&self.queue, threshold);
+    }
+
+    pub fn set_filter(&mut self, filter: PixelFilter) {
+        self.pipeline.set_filter(&self.queue, filter);
+    }
+
+    /// Adds samples to every pixel, or to those which are still too noisy
+    /// with adaptive sampling, until any of `criteria` is met.
+    pub fn render(&mut self, criteria: &StopCriteria) {
+        assert!(
+            !criteria.is_empty(),
+            "A headless render needs a stop criterion"
+        );
+        while !self.pipeline.finished(&self.device, &self.queue, criteria) {
+            let mut encoder = self
+                .device
+                .create_command_encoder(&CommandEncoderDescriptor {
+                    label: Some("Headless Encoder"),
+                });
+            self.pipeline.trace(&mut encoder);
+            self.queue.submit([encoder.finish()]);
+
+            // Waiting keeps the queue from filling up with every sample.
+            self.device.poll(Maintain::Wait);
+            self.pipeline.finish_frame(&self.queue);
+        }
+    }
+
+    /// Saves to `path`, or to a file named after the scene and the sample
+    /// count without one, and returns the file name.
+    pub fn save_exr(
+        &self,
+        path: Option<&str>,
+        precision: ExrPrecision,
+    ) -> exr::error::Result<String> {
+        self.pipeline
+            .save_exr(&self.device, &self.queue, path, precision)
+    }
+}
@@ -1,8 +1,13 @@
+mod headless;
 mod pipeline;
 
+pub use headless::Headless;
 use pipeline::Pipeline;
+pub use pipeline::{
+    ConductorPreset, ExrPrecision, FilterKind, MaterialStorage, PixelFilter, Scene, StopCriteria,
+};
 use wgpu::{
-    Backends, CommandEncoderDescriptor, Device, DeviceDescriptor, Features, Instance,
+    Adapter, Backends, CommandEncoderDescriptor, Device, DeviceDescriptor, Features, Instance,
     InstanceDescriptor, Limits, PowerPreference, Queue, RequestAdapterOptions, Surface,
     SurfaceConfiguration, SurfaceError, TextureUsages, TextureViewDescriptor,
 };
@@ -21,11 +26,34 @@ pub struct GpuState {
     prev_cursor: Option<PhysicalPosition<f64>>,
     window: Window,
     pipeline: Pipeline,
+    stop_criteria: StopCriteria,
+    /// Set once the stop criteria are met, after which no samples are
+    /// added until the camera moves.
+    finished: bool,
+    /// Where to save the render when it is finished, if at all.
+    finished_output: Option<FinishedOutput>,
+    /// Precision of the OpenEXR files `E` saves.
+    exr_precision: ExrPrecision,
+    /// The metal every conductor of the scene is made of, once cycled.
+    conductor_preset: ConductorPreset,
+}
+
+/// An OpenEXR file saved when the render is finished.
+pub struct FinishedOutput {
+    /// Named after the scene and the sample count without one.
+    path: Option<String>,
+    precision: ExrPrecision,
+}
+
+impl FinishedOutput {
+    pub fn new(path: Option<String>, precision: ExrPrecision) -> FinishedOutput {
+        FinishedOutput { path, precision }
+    }
 }
 
 impl GpuState {
     // Creating some of the wgpu types requires async code
-    pub async fn new(window: Window) -> Self {
+    pub async fn new(window: Window, scene: Scene) -> Self {
         let size = window.inner_size();
 
         // The instance is a handle to our GPU
@@ -50,28 +78,10 @@ impl GpuState {
             .await
             .unwrap();
 
-        let (device, queue) = adapter
-            .request_device(
-                &DeviceDescriptor {
-                    features: Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES,
-                    // WebGL doesn't support all of wgpu's features, so if
-                    // we're building for the web we'll have to disable some.
-                    limits: if cfg!(target_arch = "wasm32") {
-                        Limits::downlevel_webgl2_defaults()
-                    } else {
-                        Limits::default()
-                    },
-                    label: None,
-                },
-                None, // Trace path
-            )
-            .await
-            .unwrap();
+        let (device, queue) = request_device(&adapter).await;
 
         let surface_caps = surface.get_capabilities(&adapter);
-        // Shader code in this tutorial assumes an sRGB surface texture. Using a different
-        // one will result all the colors coming out darker. If you want to support non
-        // sRGB surfaces, you'll need to account for that when drawing to the frame.
+        // The display pass encodes to sRGB itself when the surface does not.
         let surface_format = surface_caps
             .formats
             .iter()
@@ -89,7 +99,7 @@ impl GpuState {
         };
         surface.configure(&device, &config);
 
-        let pipeline = Pipeline::new(&device, &config, size);
+        let pipeline = Pipeline::new(&device, &queue, config.format, size, scene);
 
         pipeline.put_random_texture(&queue);
 
@@ -102,7 +112,68 @@ impl GpuState {
             prev_cursor: None,
             window,
             pipeline,
+            stop_criteria: StopCriteria::default(),
+            finished: false,
+            finished_output: None,
+            exr_precision: ExrPrecision::Float,
+            conductor_preset: ConductorPreset::Gold,
+        }
+    }
+
+    pub fn set_stop_criteria(&mut self, criteria: StopCriteria) {
+        self.stop_criteria = criteria;
+    }
+
+    pub fn set_finished_output(&mut self, output: Option<FinishedOutput>) {
+        self.finished_output = output;
+    }
+
+    pub fn set_exr_precision(&mut self, precision: ExrPrecision) {
+        self.exr_precision = precision;
+    }
+
+    pub fn finished(&self) -> bool {
+        self.finished
+    }
+
+    pub fn set_denoiser_enabled(&mut self, enabled: bool) {
+        self.pipeline.set_denoiser_enabled(enabled);
+    }
+
+    pub fn set_adaptive_sampling(&mut self, enabled: bool) {
+        self.pipeline.set_adaptive_sampling(&self.queue, enabled);
+    }
+
+    pub fn set_noise_threshold(&mut self, threshold: f32) {
+        self.pipeline.set_noise_threshold(&self.queue, threshold);
+    }
+
+    pub fn set_filter(&mut self, filter: PixelFilter) {
+        self.pipeline.set_filter(&self.queue, filter);
+    }
+
+    /// Replaces the material at `index` in the scene's material list and
+    /// restarts the render.
+    pub fn update_material(&mut self, index: u32, material: MaterialStorage) {
+        self.pipeline.update_material(&self.queue, index, material);
+        self.finished = false;
+    }
+
+    /// Turns every conductor of the scene into the next metal preset.
+    fn cycle_conductor_preset(&mut self) {
+        self.conductor_preset = self.conductor_preset.next();
+        let conductors: Vec<(u32, MaterialStorage)> = self
+            .pipeline
+            .materials()
+            .iter()
+            .enumerate()
+            .filter(|(_, material)| material.is_conductor())
+            .map(|(index, material)| (index as u32, *material))
+            .collect();
+        for (index, material) in conductors {
+            self.update_material(index, material.with_conductor_preset(self.conductor_preset));
         }
+        println!("Conductors: {:?}", self.conductor_preset);
     }
 
     pub fn window(&self) -> &Window {
@@ -123,6 +194,13 @@ impl GpuState {
     }
 
     pub fn input(&mut self, event: &WindowEvent) -> bool {
+        // Display settings only change how the samples are shown, so they
+        // are kept. A finished render is only redrawn on input.
+        if self.display_input(event) {
+            self.window.request_redraw();
+            return true;
+        }
+
         let event_captured = match event {
             WindowEvent::KeyboardInput {
                 input:
@@ -194,12 +272,121 @@ impl GpuState {
         };
 
         if event_captured {
-            self.pipeline.reset_sample_count(&self.queue);
+            self.pipeline.camera_moved(&self.queue);
+            self.finished = false;
         }
 
         event_captured
     }
 
+    fn display_input(&mut self, event: &WindowEvent) -> bool {
+        let WindowEvent::KeyboardInput {
+            input:
+                KeyboardInput {
+                    state: ElementState::Pressed,
+                    virtual_keycode: Some(key),
+                    ..
+                },
+            ..
+        } = event
+        else {
+            return false;
+        };
+
+        match key {
+            VirtualKeyCode::Equals | VirtualKeyCode::NumpadAdd => {
+                self.pipeline.adjust_exposure(&self.queue, 0.5);
+                true
+            }
+            VirtualKeyCode::Minus | VirtualKeyCode::NumpadSubtract => {
+                self.pipeline.adjust_exposure(&self.queue, -0.5);
+                true
+            }
+            VirtualKeyCode::T => {
+                self.pipeline.cycle_tone_mapping(&self.queue);
+                true
+            }
+            VirtualKeyCode::V => {
+                self.pipeline.cycle_view(&self.queue);
+                true
+            }
+            VirtualKeyCode::R => {
+                self.pipeline.toggle_reprojection();
+                true
+            }
+            VirtualKeyCode::A => {
+                self.pipeline.toggle_adaptive_sampling(&self.queue);
+                true
+            }
+            VirtualKeyCode::Key0 => {
+                self.pipeline.scale_noise_threshold(&self.queue, 2.0);
+                true
+            }
+            VirtualKeyCode::Key9 => {
+                self.pipeline.scale_noise_threshold(&self.queue, 0.5);
+                true
+            }
+            // The filter changes the samples, so these restart them.
+            VirtualKeyCode::F => {
+                self.pipeline.cycle_filter(&self.queue);
+                self.finished = false;
+                true
+            }
+            VirtualKeyCode::Key8 => {
+                self.pipeline.adjust_filter_radius(&self.queue, 0.25);
+                self.finished = false;
+                true
+            }
+            VirtualKeyCode::Key7 => {
+                self.pipeline.adjust_filter_radius(&self.queue, -0.25);
+                self.finished = false;
+                true
+            }
+            VirtualKeyCode::M => {
+                self.cycle_conductor_preset();
+                true
+            }
+            VirtualKeyCode::D => {
+                self.pipeline.toggle_denoiser();
+                true
+            }
+            VirtualKeyCode::RBracket => {
+                self.pipeline.adjust_denoiser_iterations(&self.queue, 1);
+                true
+            }
+            VirtualKeyCode::LBracket => {
+                self.pipeline.adjust_denoiser_iterations(&self.queue, -1);
+                true
+            }
+            VirtualKeyCode::Period => {
+                self.pipeline.scale_denoiser_color_sigma(&self.queue, 2.0);
+                true
+            }
+            VirtualKeyCode::Comma => {
+                self.pipeline.scale_denoiser_color_sigma(&self.queue, 0.5);
+                true
+            }
+            VirtualKeyCode::E => {
+                match self
+                    .pipeline
+                    .save_exr(&self.device, &self.queue, None, self.exr_precision)
+                {
+                    Ok(path) => println!("Saved {}", path),
+                    Err(e) => eprintln!("Failed to save OpenEXR file: {}", e),
+                }
+                true
+            }
+            VirtualKeyCode::P => {
+                match self.pipeline.save_screenshot(&self.device, &self.queue) {
+                    Ok(path) => println!("Saved {}", path),
+                    Err(e) => eprintln!("Failed to save screenshot: {}", e),
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
     pub fn update(&mut self) {
         self.pipeline.update_camera(&self.queue);
     }
@@ -216,14 +403,60 @@ impl GpuState {
                 label: Some("Render Encoder"),
             });
 
-        self.pipeline.switch_buffer();
-        self.pipeline.render(&mut encoder, &view);
+        if self.finished {
+            self.pipeline.present(&mut encoder, &view);
+        } else {
+            self.pipeline.render(&mut encoder, &view);
+        }
 
         // submit will accept anything that implements IntoIter
         self.queue.submit([encoder.finish()]);
         output.present();
 
-        self.pipeline.increment_sample_count(&self.queue);
+        if !self.finished {
+            self.pipeline.finish_frame(&self.queue);
+            self.finished = self
+                .pipeline
+                .finished(&self.device, &self.queue, &self.stop_criteria);
+            if self.finished {
+                self.save_finished_output();
+            }
+        }
         Ok(())
     }
+
+    fn save_finished_output(&self) {
+        let Some(output) = &self.finished_output else {
+            return;
+        };
+        match self.pipeline.save_exr(
+            &self.device,
+            &self.queue,
+            output.path.as_deref(),
+            output.precision,
+        ) {
+            Ok(path) => println!("Saved {}", path),
+            Err(e) => eprintln!("Failed to save OpenEXR file: {}", e),
+        }
+    }
+}
+
+async fn request_device(adapter: &Adapter) -> (Device, Queue) {
+    adapter
+        .request_device(
+            &DeviceDescriptor {
+                features: Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES,
+                // WebGL doesn't support all of wgpu's features, so if
+                // we're building for the web we'll have to disable some.
+                limits: if cfg!(target_arch = "wasm32") {
+                    Limits::downlevel_webgl2_defaults()
+                } else {
+                    Limits::default()
+                },
+                label: None,
+            },
+            None, // Trace path
+        )
+        .await
+        .unwrap()
 }
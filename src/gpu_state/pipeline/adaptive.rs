@@ -0,0 +1,46 @@
+use bytemuck::{Pod, Zeroable};
+
+/// Settings of adaptive sampling, which stops tracing a pixel once the
+/// relative standard error of its mean luminance falls below a threshold.
+/// The error is estimated from the samples since the camera last moved. It
+/// is off by default, so every pixel gets every sample.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct AdaptiveSettings {
+    enabled: u32,
+    /// Samples a pixel takes before its error is trusted.
+    min_samples: u32,
+    threshold: f32,
+
+    _padding: u32,
+}
+
+impl Default for AdaptiveSettings {
+    fn default() -> AdaptiveSettings {
+        AdaptiveSettings {
+            enabled: false as u32,
+            min_samples: 64,
+            threshold: 0.02,
+
+            _padding: 0,
+        }
+    }
+}
+
+impl AdaptiveSettings {
+    pub fn enabled(&self) -> bool {
+        self.enabled != 0
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled as u32;
+    }
+
+    pub fn threshold(&self) -> f32 {
+        self.threshold
+    }
+
+    pub fn set_threshold(&mut self, threshold: f32) {
+        self.threshold = threshold;
+    }
+}
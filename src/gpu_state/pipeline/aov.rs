@@ -0,0 +1,93 @@
+use super::output::ExrChannel;
+use bytemuck::{Pod, Zeroable};
+
+/// Running sums of what the paths of a pixel see at their first hit which
+/// is not a perfect reflection or refraction. Dividing by the sample count
+/// of the pixel gives the averages. The indices are those of the latest
+/// sample, or -1 when it escaped the scene.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct Aov {
+    albedo: [f32; 3],
+    /// Length of the path to the hit.
+    depth: f32,
+    normal: [f32; 3],
+    object_index: i32,
+    position: [f32; 3],
+    material_index: i32,
+}
+
+/// What the display pass shows.
+#[derive(Copy, Clone, Debug)]
+pub enum AovView {
+    Beauty,
+    Albedo,
+    Normal,
+    Depth,
+    Position,
+    ObjectIndex,
+    MaterialIndex,
+    /// How many samples every pixel took, which differ with adaptive
+    /// sampling.
+    SampleCount,
+}
+
+impl AovView {
+    pub fn next(self) -> AovView {
+        match self {
+            AovView::Beauty => AovView::Albedo,
+            AovView::Albedo => AovView::Normal,
+            AovView::Normal => AovView::Depth,
+            AovView::Depth => AovView::Position,
+            AovView::Position => AovView::ObjectIndex,
+            AovView::ObjectIndex => AovView::MaterialIndex,
+            AovView::MaterialIndex => AovView::SampleCount,
+            AovView::SampleCount => AovView::Beauty,
+        }
+    }
+
+    pub fn from_index(index: u32) -> AovView {
+        match index {
+            1 => AovView::Albedo,
+            2 => AovView::Normal,
+            3 => AovView::Depth,
+            4 => AovView::Position,
+            5 => AovView::ObjectIndex,
+            6 => AovView::MaterialIndex,
+            7 => AovView::SampleCount,
+            _ => AovView::Beauty,
+        }
+    }
+}
+
+/// Averages the sums into OpenEXR channels. `sample_counts` holds the
+/// number of samples of every pixel.
+pub fn exr_channels(aovs: &[Aov], sample_counts: &[f32]) -> Vec<ExrChannel> {
+    let average = |value: fn(&Aov) -> f32| -> Vec<f32> {
+        aovs.iter()
+            .zip(sample_counts)
+            .map(|(aov, &count)| value(aov) / count.max(1.0))
+            .collect()
+    };
+
+    vec![
+        ExrChannel::new("albedo.R", average(|aov| aov.albedo[0])),
+        ExrChannel::new("albedo.G", average(|aov| aov.albedo[1])),
+        ExrChannel::new("albedo.B", average(|aov| aov.albedo[2])),
+        ExrChannel::new("normal.X", average(|aov| aov.normal[0])),
+        ExrChannel::new("normal.Y", average(|aov| aov.normal[1])),
+        ExrChannel::new("normal.Z", average(|aov| aov.normal[2])),
+        ExrChannel::new("position.X", average(|aov| aov.position[0])),
+        ExrChannel::new("position.Y", average(|aov| aov.position[1])),
+        ExrChannel::new("position.Z", average(|aov| aov.position[2])),
+        ExrChannel::new("Z", average(|aov| aov.depth)),
+        ExrChannel::new(
+            "objectIndex",
+            aovs.iter().map(|aov| aov.object_index as f32).collect(),
+        ),
+        ExrChannel::new(
+            "materialIndex",
+            aovs.iter().map(|aov| aov.material_index as f32).collect(),
+        ),
+    ]
+}
@@ -24,7 +24,7 @@ impl Camera {
         }
     }
 
-    pub fn into_uniform(&self) -> CameraUniform {
+    pub fn into_uniform(self) -> CameraUniform {
         let true_up = Vector3::unit_z();
         let right = self.forwards.cross(true_up).normalize();
         let up = right.cross(self.forwards).normalize();
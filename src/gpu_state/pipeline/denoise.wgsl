@@ -0,0 +1,89 @@
+struct Aov {
+    albedo: vec3<f32>,
+    depth: f32,
+    normal: vec3<f32>,
+    objectIndex: i32,
+    position: vec3<f32>,
+    materialIndex: i32,
+}
+
+// Settings of one iteration of the filter
+struct Iteration {
+    width: u32,
+    height: u32,
+    // Distance in pixels between the taps of the kernel
+    stepWidth: i32,
+    colorSigma: f32,
+    normalSigma: f32,
+    depthSigma: f32,
+    albedoSigma: f32,
+}
+
+// Radiance sums in rgb and their sample counts in w. The first iteration
+// reads the accumulation, later ones the averages of the previous one.
+@group(0) @binding(0) var<storage, read> input: array<vec4<f32>>;
+@group(0) @binding(1) var<storage, read_write> output: array<vec4<f32>>;
+@group(0) @binding(2) var<storage, read> aovs: array<Aov>;
+@group(0) @binding(3) var<uniform> iteration: Iteration;
+// Holds the sample counts the auxiliary outputs are summed over
+@group(0) @binding(4) var<storage, read> accumulation: array<vec4<f32>>;
+
+// One iteration of the edge-avoiding A-Trous wavelet filter of Dammertz et
+// al. The 5x5 B3 spline kernel is spread further apart every iteration, and
+// taps across edges in the color, normal, depth or albedo are ignored.
+@compute @workgroup_size(8, 8, 1)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= iteration.width || id.y >= iteration.height {
+        return;
+    }
+
+    let center = i32(id.y * iteration.width + id.x);
+    let samples = max(input[center].w, 1.0);
+    let color = input[center].rgb / samples;
+    let guide = average_aov(center);
+
+    var kernel = array<f32, 3>(3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0);
+
+    var sum = vec3<f32>(0.0);
+    var weightSum = 0.0;
+    for (var y = -2; y <= 2; y++) {
+        for (var x = -2; x <= 2; x++) {
+            let tap = vec2<i32>(id.xy) + vec2<i32>(x, y) * iteration.stepWidth;
+            if tap.x < 0 || tap.y < 0 || tap.x >= i32(iteration.width) || tap.y >= i32(iteration.height) {
+                continue;
+            }
+
+            let index = tap.y * i32(iteration.width) + tap.x;
+            let tapColor = input[index].rgb / max(input[index].w, 1.0);
+            let tapGuide = average_aov(index);
+
+            let colorDifference = tapColor - color;
+            let colorWeight = exp(-dot(colorDifference, colorDifference) / (iteration.colorSigma * iteration.colorSigma));
+
+            let normalDifference = tapGuide.normal - guide.normal;
+            let normalWeight = exp(-dot(normalDifference, normalDifference) / (iteration.normalSigma * iteration.normalSigma));
+
+            let depthDifference = abs(tapGuide.depth - guide.depth);
+            let depthWeight = exp(-depthDifference / (iteration.depthSigma * f32(iteration.stepWidth)));
+
+            let albedoDifference = tapGuide.albedo - guide.albedo;
+            let albedoWeight = exp(-dot(albedoDifference, albedoDifference) / (iteration.albedoSigma * iteration.albedoSigma));
+
+            let weight = kernel[abs(x)] * kernel[abs(y)] * colorWeight * normalWeight * depthWeight * albedoWeight;
+            sum += tapColor * weight;
+            weightSum += weight;
+        }
+    }
+
+    output[center] = vec4<f32>(sum / max(weightSum, 1e-6), 1.0);
+}
+
+fn average_aov(index: i32) -> Aov {
+    var aov = aovs[index];
+    let samples = max(accumulation[index].w, 1.0);
+    aov.albedo /= samples;
+    aov.depth /= samples;
+    aov.normal /= samples;
+    aov.position /= samples;
+    return aov;
+}
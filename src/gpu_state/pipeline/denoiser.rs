@@ -0,0 +1,268 @@
+use bytemuck::{Pod, Zeroable};
+use std::mem::size_of;
+use std::num::NonZeroU64;
+use wgpu::{
+    BindGroup, BindGroupLayout, Buffer, BufferBinding, CommandEncoder, ComputePassDescriptor,
+    ComputePipeline, ComputePipelineDescriptor, Device, Extent3d, PipelineLayoutDescriptor, Queue,
+    ShaderModuleDescriptor, ShaderSource,
+};
+
+/// Most iterations the filter can run, spreading its kernel over 2^7 pixels.
+pub const MAX_ITERATIONS: u32 = 8;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct IterationUniform {
+    width: u32,
+    height: u32,
+    step_width: i32,
+    color_sigma: f32,
+    normal_sigma: f32,
+    depth_sigma: f32,
+    albedo_sigma: f32,
+
+    _padding: u32,
+}
+
+/// Edge-avoiding A-Trous wavelet filter, guided by the albedo, normal and
+/// depth outputs, which turns the accumulated samples into a smooth image.
+/// It filters into one of two buffers in turns, so which one holds the
+/// result depends on the number of iterations.
+pub struct Denoiser {
+    size: Extent3d,
+    enabled: bool,
+    iterations: u32,
+    /// How different the colors of two pixels can be before they are not
+    /// blended, halved every iteration.
+    color_sigma: f32,
+    normal_sigma: f32,
+    /// Per pixel of distance between the taps.
+    depth_sigma: f32,
+    albedo_sigma: f32,
+    iteration_buffer: Buffer,
+    /// Distance between the settings of consecutive iterations in the
+    /// iteration buffer.
+    iteration_stride: u32,
+    first_bind_group: BindGroup,
+    /// `bind_groups[i]` filters from output buffer `i` into the other.
+    bind_groups: [BindGroup; 2],
+    pipeline: ComputePipeline,
+}
+
+impl Denoiser {
+    /// `outputs` are the two buffers filtered into, each the size of
+    /// `accumulation`.
+    pub fn new(
+        device: &Device,
+        size: Extent3d,
+        accumulation: &Buffer,
+        aovs: &Buffer,
+        outputs: &[Buffer; 2],
+    ) -> Denoiser {
+        let iteration_stride = device
+            .limits()
+            .min_uniform_buffer_offset_alignment
+            .max(size_of::<IterationUniform>() as u32);
+
+        let iteration_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Denoiser Iteration Buffer"),
+            size: (iteration_stride * MAX_ITERATIONS) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
+            mapped_at_creation: false,
+        });
+
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Denoise Shader"),
+            source: ShaderSource::Wgsl(include_str!("denoise.wgsl").into()),
+        });
+
+        let storage_entry = |binding: u32, read_only: bool| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                storage_entry(0, true),
+                storage_entry(1, false),
+                storage_entry(2, true),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: NonZeroU64::new(size_of::<IterationUniform>() as u64),
+                    },
+                    count: None,
+                },
+                storage_entry(4, true),
+            ],
+            label: Some("denoiser_bind_group_layout"),
+        });
+
+        let create_bind_group = |input: &Buffer, output: &Buffer| {
+            Denoiser::create_bind_group(
+                device,
+                &bind_group_layout,
+                [input, output, aovs, accumulation],
+                &iteration_buffer,
+            )
+        };
+        let first_bind_group = create_bind_group(accumulation, &outputs[0]);
+        let bind_groups = [
+            create_bind_group(&outputs[0], &outputs[1]),
+            create_bind_group(&outputs[1], &outputs[0]),
+        ];
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Denoiser Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: Some("Denoiser Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: "main",
+        });
+
+        Denoiser {
+            size,
+            enabled: false,
+            iterations: 5,
+            color_sigma: 1.0,
+            normal_sigma: 0.3,
+            depth_sigma: 0.05,
+            albedo_sigma: 0.1,
+            iteration_buffer,
+            iteration_stride,
+            first_bind_group,
+            bind_groups,
+            pipeline,
+        }
+    }
+
+    /// `buffers` are the input, the output, the auxiliary outputs and the
+    /// accumulation.
+    fn create_bind_group(
+        device: &Device,
+        layout: &BindGroupLayout,
+        buffers: [&Buffer; 4],
+        iteration_buffer: &Buffer,
+    ) -> BindGroup {
+        let [input, output, aovs, accumulation] = buffers;
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: input.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: output.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: aovs.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Buffer(BufferBinding {
+                        buffer: iteration_buffer,
+                        offset: 0,
+                        size: NonZeroU64::new(size_of::<IterationUniform>() as u64),
+                    }),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: accumulation.as_entire_binding(),
+                },
+            ],
+            label: Some("denoiser_bind_group"),
+        })
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn iterations(&self) -> u32 {
+        self.iterations
+    }
+
+    /// Clamped between 1 and `MAX_ITERATIONS`.
+    pub fn set_iterations(&mut self, iterations: u32) {
+        self.iterations = iterations.clamp(1, MAX_ITERATIONS);
+    }
+
+    pub fn color_sigma(&self) -> f32 {
+        self.color_sigma
+    }
+
+    pub fn set_color_sigma(&mut self, color_sigma: f32) {
+        self.color_sigma = color_sigma;
+    }
+
+    /// Index of the output buffer which holds the result of the last
+    /// iteration.
+    pub fn output_index(&self) -> usize {
+        ((self.iterations - 1) % 2) as usize
+    }
+
+    /// Uploads the settings, which have to be uploaded after changing them.
+    pub fn update(&self, queue: &Queue) {
+        let mut data = vec![0u8; (self.iteration_stride * MAX_ITERATIONS) as usize];
+        for i in 0..MAX_ITERATIONS {
+            let iteration = IterationUniform {
+                width: self.size.width,
+                height: self.size.height,
+                step_width: 1 << i,
+                color_sigma: self.color_sigma / (1 << i) as f32,
+                normal_sigma: self.normal_sigma,
+                depth_sigma: self.depth_sigma,
+                albedo_sigma: self.albedo_sigma,
+
+                _padding: 0,
+            };
+            let offset = (i * self.iteration_stride) as usize;
+            data[offset..offset + size_of::<IterationUniform>()]
+                .copy_from_slice(bytemuck::bytes_of(&iteration));
+        }
+        queue.write_buffer(&self.iteration_buffer, 0, &data);
+    }
+
+    pub fn run(&self, encoder: &mut CommandEncoder) {
+        let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("Denoise Pass"),
+        });
+        compute_pass.set_pipeline(&self.pipeline);
+
+        for i in 0..self.iterations {
+            let bind_group = if i == 0 {
+                &self.first_bind_group
+            } else {
+                // Iteration i - 1 wrote into output (i - 1) % 2.
+                &self.bind_groups[((i - 1) % 2) as usize]
+            };
+            compute_pass.set_bind_group(0, bind_group, &[i * self.iteration_stride]);
+            compute_pass.dispatch_workgroups(
+                self.size.width.div_ceil(8),
+                self.size.height.div_ceil(8),
+                1,
+            );
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use super::aov::AovView;
+use bytemuck::{Pod, Zeroable};
+
+/// Operators which compress the unbounded radiance of the render into the
+/// displayable range.
+#[derive(Copy, Clone, Debug)]
+pub enum ToneMapping {
+    /// Cuts off everything above 1.
+    Clamp,
+    Reinhard,
+    /// The fit of the ACES filmic curve by Krzysztof Narkowicz.
+    AcesFilmic,
+    /// Desaturates bright colors towards white instead of skewing their hue.
+    AgX,
+}
+
+impl ToneMapping {
+    pub fn next(self) -> ToneMapping {
+        match self {
+            ToneMapping::Clamp => ToneMapping::Reinhard,
+            ToneMapping::Reinhard => ToneMapping::AcesFilmic,
+            ToneMapping::AcesFilmic => ToneMapping::AgX,
+            ToneMapping::AgX => ToneMapping::Clamp,
+        }
+    }
+
+    fn from_index(index: u32) -> ToneMapping {
+        match index {
+            1 => ToneMapping::Reinhard,
+            2 => ToneMapping::AcesFilmic,
+            3 => ToneMapping::AgX,
+            _ => ToneMapping::Clamp,
+        }
+    }
+}
+
+/// How the display pass turns the accumulated samples into the image on
+/// screen.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct DisplaySettings {
+    /// Size of the accumulation buffer in pixels.
+    width: u32,
+    height: u32,
+    /// Stops the radiance is brightened by before tone mapping.
+    exposure: f32,
+    tone_mapping: u32,
+    /// Set when the surface does not encode to sRGB by itself, so the
+    /// shader has to.
+    encode_srgb: u32,
+    view: u32,
+    /// Most samples a pixel can have, which the sample count view is scaled
+    /// by.
+    sample_count: u32,
+
+    _padding: u32,
+}
+
+impl DisplaySettings {
+    pub fn new(width: u32, height: u32, encode_srgb: bool) -> DisplaySettings {
+        DisplaySettings {
+            width,
+            height,
+            exposure: 0.0,
+            tone_mapping: ToneMapping::AcesFilmic as u32,
+            encode_srgb: encode_srgb as u32,
+            view: AovView::Beauty as u32,
+            sample_count: 0,
+
+            _padding: 0,
+        }
+    }
+
+    pub fn exposure(&self) -> f32 {
+        self.exposure
+    }
+
+    pub fn set_exposure(&mut self, exposure: f32) {
+        self.exposure = exposure;
+    }
+
+    pub fn tone_mapping(&self) -> ToneMapping {
+        ToneMapping::from_index(self.tone_mapping)
+    }
+
+    pub fn set_tone_mapping(&mut self, tone_mapping: ToneMapping) {
+        self.tone_mapping = tone_mapping as u32;
+    }
+
+    pub fn view(&self) -> AovView {
+        AovView::from_index(self.view)
+    }
+
+    pub fn set_view(&mut self, view: AovView) {
+        self.view = view as u32;
+    }
+
+    pub fn set_sample_count(&mut self, sample_count: u32) {
+        self.sample_count = sample_count;
+    }
+}
@@ -0,0 +1,229 @@
+use bytemuck::{Pod, Zeroable};
+use std::f32::consts::PI;
+
+/// Number of bins the filters are tabulated in for sampling.
+const FILTER_BINS: usize = 64;
+
+/// Reconstruction filters, which weigh the samples of a pixel by their
+/// distance to its center. They are separable, so the 2D filter is the
+/// product of the 1D one along both axes.
+#[derive(Copy, Clone, Debug)]
+pub enum FilterKind {
+    Box,
+    Tent,
+    Gaussian,
+    BlackmanHarris,
+    /// With B = C = 1/3. Its negative lobes sharpen the image.
+    MitchellNetravali,
+}
+
+impl FilterKind {
+    pub fn next(self) -> FilterKind {
+        match self {
+            FilterKind::Box => FilterKind::Tent,
+            FilterKind::Tent => FilterKind::Gaussian,
+            FilterKind::Gaussian => FilterKind::BlackmanHarris,
+            FilterKind::BlackmanHarris => FilterKind::MitchellNetravali,
+            FilterKind::MitchellNetravali => FilterKind::Box,
+        }
+    }
+
+    /// Radius in pixels the filter is usually used with.
+    pub fn default_radius(self) -> f32 {
+        match self {
+            FilterKind::Box => 0.5,
+            FilterKind::Tent => 1.0,
+            FilterKind::Gaussian => 1.5,
+            FilterKind::BlackmanHarris => 2.0,
+            FilterKind::MitchellNetravali => 2.0,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<FilterKind> {
+        match name {
+            "box" => Some(FilterKind::Box),
+            "tent" => Some(FilterKind::Tent),
+            "gaussian" => Some(FilterKind::Gaussian),
+            "blackman-harris" => Some(FilterKind::BlackmanHarris),
+            "mitchell" => Some(FilterKind::MitchellNetravali),
+            _ => None,
+        }
+    }
+}
+
+/// Tabulated filter, from which the compute shader samples the offsets of
+/// the samples from the pixel centers. Sampling proportionally to the
+/// filter is unbiased without dividing by the sum of the weights, so every
+/// sample still counts as one.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct FilterUniform {
+    radius: f32,
+    /// Factor of the samples, which is the integral of the absolute filter
+    /// over that of the filter, and above 1 only with negative lobes.
+    weight_scale: f32,
+
+    _padding: [u32; 2],
+    /// Cumulative distribution of the absolute filter over equally wide
+    /// bins across the diameter, and the sign of the filter in the bin.
+    bins: [[f32; 4]; FILTER_BINS],
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct PixelFilter {
+    kind: FilterKind,
+    /// In pixels.
+    radius: f32,
+}
+
+/// A box one pixel wide, which keeps renders as sharp as they were before
+/// the other filters.
+impl Default for PixelFilter {
+    fn default() -> PixelFilter {
+        PixelFilter::new(FilterKind::Box)
+    }
+}
+
+impl PixelFilter {
+    pub fn new(kind: FilterKind) -> PixelFilter {
+        PixelFilter {
+            kind,
+            radius: kind.default_radius(),
+        }
+    }
+
+    /// `radius` is in pixels and has to be positive.
+    pub fn with_radius(mut self, radius: f32) -> PixelFilter {
+        assert!(
+            radius > 0.0,
+            "Filter radius has to be positive, not {}",
+            radius
+        );
+        self.radius = radius;
+        self
+    }
+
+    pub fn kind(&self) -> FilterKind {
+        self.kind
+    }
+
+    pub fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    /// The 1D filter at `x` pixels from the center.
+    fn evaluate(&self, x: f32) -> f32 {
+        let r = self.radius;
+        if x.abs() > r {
+            return 0.0;
+        }
+
+        match self.kind {
+            FilterKind::Box => 1.0,
+            FilterKind::Tent => 1.0 - x.abs() / r,
+            FilterKind::Gaussian => {
+                // Shifted down to reach 0 at the radius.
+                let sigma = r / 3.0;
+                let gaussian = |x: f32| (-x * x / (2.0 * sigma * sigma)).exp();
+                gaussian(x) - gaussian(r)
+            }
+            FilterKind::BlackmanHarris => {
+                let t = 2.0 * PI * (x + r) / (2.0 * r);
+                0.35875 - 0.48829 * t.cos() + 0.14128 * (2.0 * t).cos() - 0.01168 * (3.0 * t).cos()
+            }
+            FilterKind::MitchellNetravali => {
+                let (b, c) = (1.0 / 3.0, 1.0 / 3.0);
+                let x = 2.0 * x.abs() / r;
+                if x > 1.0 {
+                    ((-b - 6.0 * c) * x * x * x
+                        + (6.0 * b + 30.0 * c) * x * x
+                        + (-12.0 * b - 48.0 * c) * x
+                        + (8.0 * b + 24.0 * c))
+                        / 6.0
+                } else {
+                    ((12.0 - 9.0 * b - 6.0 * c) * x * x * x
+                        + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+                        + (6.0 - 2.0 * b))
+                        / 6.0
+                }
+            }
+        }
+    }
+
+    pub fn into_uniform(self) -> FilterUniform {
+        let width = 2.0 * self.radius / FILTER_BINS as f32;
+        let values: Vec<f32> = (0..FILTER_BINS)
+            .map(|i| self.evaluate(-self.radius + (i as f32 + 0.5) * width))
+            .collect();
+
+        let absolute_sum: f32 = values.iter().map(|value| value.abs()).sum();
+        let sum: f32 = values.iter().sum();
+
+        let mut bins = [[0.0; 4]; FILTER_BINS];
+        let mut cumulative = 0.0;
+        for (bin, value) in bins.iter_mut().zip(&values) {
+            cumulative += value.abs() / absolute_sum;
+            *bin = [cumulative, value.signum(), 0.0, 0.0];
+        }
+        // Rounding must not leave the last bin short of 1.
+        bins[FILTER_BINS - 1][0] = 1.0;
+
+        FilterUniform {
+            radius: self.radius,
+            weight_scale: (absolute_sum / sum).powi(2),
+
+            _padding: [0; 2],
+            bins,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KINDS: [FilterKind; 5] = [
+        FilterKind::Box,
+        FilterKind::Tent,
+        FilterKind::Gaussian,
+        FilterKind::BlackmanHarris,
+        FilterKind::MitchellNetravali,
+    ];
+
+    #[test]
+    fn cumulative_distribution_rises_to_one() {
+        for kind in KINDS {
+            let uniform = PixelFilter::new(kind).into_uniform();
+            assert!(uniform.bins.windows(2).all(|bins| bins[0][0] <= bins[1][0]));
+            assert_eq!(uniform.bins[FILTER_BINS - 1][0], 1.0, "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn positive_filters_do_not_scale_the_samples() {
+        for kind in [FilterKind::Box, FilterKind::Tent] {
+            let uniform = PixelFilter::new(kind).into_uniform();
+            assert!((uniform.weight_scale - 1.0).abs() < 1e-6, "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn negative_lobes_scale_the_samples_up() {
+        let uniform = PixelFilter::new(FilterKind::MitchellNetravali).into_uniform();
+        assert!(uniform.weight_scale > 1.0);
+        assert!(uniform.bins.iter().any(|bin| bin[1] < 0.0));
+    }
+
+    #[test]
+    fn default_is_a_one_pixel_box() {
+        let filter = PixelFilter::default();
+        assert!(matches!(filter.kind(), FilterKind::Box));
+        assert_eq!(filter.radius(), 0.5);
+    }
+
+    #[test]
+    #[should_panic]
+    fn radius_has_to_be_positive() {
+        PixelFilter::new(FilterKind::Gaussian).with_radius(0.0);
+    }
+}
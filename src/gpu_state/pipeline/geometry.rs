@@ -1,4 +1,3 @@
-use super::material::MaterialStorage;
 use bytemuck::{Pod, Zeroable};
 
 #[repr(C)]
@@ -7,10 +6,9 @@ pub struct GeometryStorage {
     center: [f32; 3],
     radius: f32,
     u: [f32; 3],
-    _padding: f32,
+    material: u32,
     v: [f32; 3],
     kind: u32,
-    material: MaterialStorage,
 }
 
 impl GeometryStorage {
@@ -20,32 +18,34 @@ impl GeometryStorage {
         u: [f32; 3],
         v: [f32; 3],
         kind: u32,
-        material: MaterialStorage,
+        material: u32,
     ) -> GeometryStorage {
         GeometryStorage {
             center,
             radius,
             u,
+            material,
             v,
             kind,
-            material,
-
-            _padding: 0.0,
         }
     }
 
-    pub fn new_sphere(center: [f32; 3], radius: f32, material: MaterialStorage) -> GeometryStorage {
+    /// `material` is the index returned by `Scene::add_material`.
+    pub fn new_sphere(center: [f32; 3], radius: f32, material: u32) -> GeometryStorage {
         GeometryStorage::new(center, radius, [0.0; 3], [0.0; 3], 0, material)
     }
 
     /// `corner` is a position vector of one corner of the parallelogram.
     /// `u` and `v` are length vectors for the two arms of the parallelogram.
-    pub fn new_quad(
-        corner: [f32; 3],
-        u: [f32; 3],
-        v: [f32; 3],
-        material: MaterialStorage,
-    ) -> GeometryStorage {
+    pub fn new_quad(corner: [f32; 3], u: [f32; 3], v: [f32; 3], material: u32) -> GeometryStorage {
         GeometryStorage::new(corner, 0.0, u, v, 1, material)
     }
+
+    pub fn is_quad(&self) -> bool {
+        self.kind == 1
+    }
+
+    pub fn material(&self) -> u32 {
+        self.material
+    }
 }
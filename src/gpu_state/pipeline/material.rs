@@ -1,10 +1,125 @@
 use bytemuck::{Pod, Zeroable};
 
+/// Texture index of a material parameter which is not textured.
+const NO_TEXTURE: i32 = -1;
+
+/// Material index of the medium inside a dielectric which has none.
+const NO_MEDIUM: i32 = -1;
+
 pub enum MaterialKind {
     Lambertian,
-    Metallic { fuzz: f32 },
-    Dielectric { refractive_index: f32 },
+    Metallic {
+        fuzz: f32,
+    },
+    Dielectric {
+        refractive_index: f32,
+    },
     Light,
+    /// The boundary of a volume of homogeneous participating media. The
+    /// color of the material is its scattering coefficient. `anisotropy` is
+    /// the Henyey-Greenstein asymmetry, from -1 for back scattering to 1
+    /// for forward scattering.
+    Medium {
+        absorption: [f32; 3],
+        anisotropy: f32,
+    },
+    /// A dielectric boundary around a scattering interior, for skin, wax or
+    /// marble. The color of the material is the albedo of the interior and
+    /// `mean_free_path` is the average distance light travels through it
+    /// between collisions, per color channel.
+    Subsurface {
+        refractive_index: f32,
+        mean_free_path: [f32; 3],
+        anisotropy: f32,
+    },
+    /// A GGX conductor whose roughness differs along the tangent and the
+    /// bitangent, for brushed metal. The tangent follows the `u` arm of a
+    /// quad or the direction around the z axis on a sphere, turned around
+    /// the normal by `rotation` radians. The color of the material is the
+    /// reflectance at normal incidence.
+    AnisotropicMetallic {
+        roughness_tangent: f32,
+        roughness_bitangent: f32,
+        rotation: f32,
+    },
+    /// Fabric with a Lambertian base of the material color under a Charlie
+    /// sheen lobe, which brightens it at grazing angles like velvet.
+    /// `sheen_roughness` from 0 to 1 spreads the sheen away from the
+    /// grazing angles.
+    Cloth {
+        sheen_color: [f32; 3],
+        sheen_roughness: f32,
+    },
+    /// A GGX conductor defined by its complex refractive index `eta + ik`
+    /// per color channel, reflecting with the exact conductor Fresnel. The
+    /// color of the material tints the reflection and is white for a
+    /// physically based metal.
+    Conductor {
+        eta: [f32; 3],
+        k: [f32; 3],
+        roughness: f32,
+    },
+    /// A BRDF measured by MERL, looked up from the table at index `table` in
+    /// the scene's measured BRDF list. The color of the material tints the
+    /// reflection and is white to reproduce the measurement.
+    Measured {
+        table: u32,
+    },
+    /// Blends the materials at indices `first` and `second` by picking the
+    /// second at each hit with a probability of the factor, so it works
+    /// with every other kind of material. The color of a mix is unused.
+    Mix {
+        first: u32,
+        second: u32,
+        factor: MixFactor,
+    },
+}
+
+/// How much of the second material of a mix shows at a hit.
+pub enum MixFactor {
+    Constant(f32),
+    /// The red channel of the texture at index `texture` in the scene's
+    /// texture list.
+    Texture(u32),
+    /// The Schlick reflectance of a dielectric with the refractive index, so
+    /// the second material shows at grazing angles, as dust on plastic.
+    Fresnel(f32),
+}
+
+/// Metals with measured complex refractive indices, averaged over the red,
+/// green and blue responses of the eye rather than sampled at single
+/// wavelengths.
+#[derive(Copy, Clone, Debug)]
+pub enum ConductorPreset {
+    Gold,
+    Silver,
+    Copper,
+    Aluminum,
+    Chrome,
+}
+
+impl ConductorPreset {
+    pub fn next(self) -> ConductorPreset {
+        match self {
+            ConductorPreset::Gold => ConductorPreset::Silver,
+            ConductorPreset::Silver => ConductorPreset::Copper,
+            ConductorPreset::Copper => ConductorPreset::Aluminum,
+            ConductorPreset::Aluminum => ConductorPreset::Chrome,
+            ConductorPreset::Chrome => ConductorPreset::Gold,
+        }
+    }
+
+    /// Returns the real part `eta` and the imaginary part `k` of the
+    /// refractive index.
+    pub fn refractive_index(&self) -> ([f32; 3], [f32; 3]) {
+        match self {
+            ConductorPreset::Gold => ([0.143, 0.374, 1.442], [3.983, 2.385, 1.603]),
+            ConductorPreset::Silver => ([0.155, 0.117, 0.138], [4.828, 3.122, 2.147]),
+            ConductorPreset::Copper => ([0.200, 0.924, 1.102], [3.912, 2.452, 2.142]),
+            ConductorPreset::Aluminum => ([1.657, 0.880, 0.521], [9.224, 6.270, 4.837]),
+            ConductorPreset::Chrome => ([3.110, 3.180, 2.320], [3.310, 3.330, 3.135]),
+        }
+    }
 }
 
 #[repr(C)]
@@ -13,32 +128,165 @@ pub struct MaterialStorage {
     color: [f32; 3],
     kind: u32,
     fuzz_or_refractive_index: f32,
-    _padding: [f32; 3],
+    albedo_texture: i32,
+    roughness_texture: i32,
+    emission_texture: i32,
+    normal_texture: i32,
+    bump_texture: i32,
+    bump_strength: f32,
+    alpha: f32,
+    alpha_texture: i32,
+    clearcoat: f32,
+    clearcoat_roughness: f32,
+    film_thickness: f32,
+    film_refractive_index: f32,
+    interior_medium: i32,
+    priority: u32,
+    _padding: f32,
+    absorption: [f32; 3],
+    anisotropy: f32,
+    mean_free_path: [f32; 3],
+    roughness_tangent: f32,
+    roughness_bitangent: f32,
+    tangent_rotation: f32,
+    _roughness_padding: [f32; 2],
+    sheen_color: [f32; 3],
+    sheen_roughness: f32,
+    conductor_eta: [f32; 3],
+    measured_brdf: u32,
+    conductor_k: [f32; 3],
+    mix_texture: i32,
+    mix_materials: [u32; 2],
+    mix_factor_kind: u32,
+    mix_factor: f32,
 }
 
 impl MaterialStorage {
-    fn new_with_padding(
-        color: [f32; 3],
-        kind: u32,
-        fuzz_or_refractive_index: f32,
-    ) -> MaterialStorage {
+    fn new_with_defaults(color: [f32; 3], kind: u32) -> MaterialStorage {
         MaterialStorage {
             color,
             kind,
-            fuzz_or_refractive_index,
+            fuzz_or_refractive_index: 0.0,
+            albedo_texture: NO_TEXTURE,
+            roughness_texture: NO_TEXTURE,
+            emission_texture: NO_TEXTURE,
+            normal_texture: NO_TEXTURE,
+            bump_texture: NO_TEXTURE,
+            bump_strength: 0.0,
+            alpha: 1.0,
+            alpha_texture: NO_TEXTURE,
+            clearcoat: 0.0,
+            clearcoat_roughness: 0.0,
+            film_thickness: 0.0,
+            film_refractive_index: 1.0,
+            interior_medium: NO_MEDIUM,
+            priority: 0,
+            absorption: [0.0; 3],
+            anisotropy: 0.0,
+            mean_free_path: [0.0; 3],
+            roughness_tangent: 0.0,
+            roughness_bitangent: 0.0,
+            tangent_rotation: 0.0,
+            sheen_color: [0.0; 3],
+            sheen_roughness: 0.0,
+            conductor_eta: [0.0; 3],
+            measured_brdf: 0,
+            conductor_k: [0.0; 3],
+            mix_texture: NO_TEXTURE,
+            mix_materials: [0; 2],
+            mix_factor_kind: 0,
+            mix_factor: 0.0,
 
-            _padding: [0.0, 0.0, 0.0],
+            _padding: 0.0,
+            _roughness_padding: [0.0; 2],
         }
     }
 
     pub fn new(color: [f32; 3], kind: MaterialKind) -> MaterialStorage {
         match kind {
-            MaterialKind::Lambertian => MaterialStorage::new_with_padding(color, 0, 0.0),
-            MaterialKind::Metallic { fuzz } => MaterialStorage::new_with_padding(color, 1, fuzz),
-            MaterialKind::Dielectric { refractive_index } => {
-                MaterialStorage::new_with_padding(color, 2, refractive_index)
+            MaterialKind::Lambertian => MaterialStorage::new_with_defaults(color, 0),
+            MaterialKind::Metallic { fuzz } => MaterialStorage {
+                fuzz_or_refractive_index: fuzz,
+                ..MaterialStorage::new_with_defaults(color, 1)
+            },
+            MaterialKind::Dielectric { refractive_index } => MaterialStorage {
+                fuzz_or_refractive_index: refractive_index,
+                ..MaterialStorage::new_with_defaults(color, 2)
+            },
+            MaterialKind::Light => MaterialStorage::new_with_defaults(color, 3),
+            MaterialKind::Medium {
+                absorption,
+                anisotropy,
+            } => MaterialStorage {
+                absorption,
+                anisotropy,
+                ..MaterialStorage::new_with_defaults(color, 4)
+            },
+            MaterialKind::Subsurface {
+                refractive_index,
+                mean_free_path,
+                anisotropy,
+            } => MaterialStorage {
+                fuzz_or_refractive_index: refractive_index,
+                mean_free_path,
+                anisotropy,
+                ..MaterialStorage::new_with_defaults(color, 5)
+            },
+            MaterialKind::AnisotropicMetallic {
+                roughness_tangent,
+                roughness_bitangent,
+                rotation,
+            } => MaterialStorage {
+                roughness_tangent,
+                roughness_bitangent,
+                tangent_rotation: rotation,
+                ..MaterialStorage::new_with_defaults(color, 6)
+            },
+            MaterialKind::Cloth {
+                sheen_color,
+                sheen_roughness,
+            } => MaterialStorage {
+                sheen_color,
+                sheen_roughness,
+                ..MaterialStorage::new_with_defaults(color, 7)
+            },
+            MaterialKind::Conductor { eta, k, roughness } => MaterialStorage {
+                roughness_tangent: roughness,
+                roughness_bitangent: roughness,
+                conductor_eta: eta,
+                conductor_k: k,
+                ..MaterialStorage::new_with_defaults(color, 8)
+            },
+            MaterialKind::Measured { table } => MaterialStorage {
+                measured_brdf: table,
+                ..MaterialStorage::new_with_defaults(color, 9)
+            },
+            MaterialKind::Mix {
+                first,
+                second,
+                factor,
+            } => {
+                let mix = MaterialStorage {
+                    mix_materials: [first, second],
+                    ..MaterialStorage::new_with_defaults(color, 10)
+                };
+                match factor {
+                    MixFactor::Constant(factor) => MaterialStorage {
+                        mix_factor: factor,
+                        ..mix
+                    },
+                    MixFactor::Texture(texture) => MaterialStorage {
+                        mix_texture: texture as i32,
+                        mix_factor_kind: 1,
+                        ..mix
+                    },
+                    MixFactor::Fresnel(refractive_index) => MaterialStorage {
+                        mix_factor: refractive_index,
+                        mix_factor_kind: 2,
+                        ..mix
+                    },
+                }
             }
-            MaterialKind::Light => MaterialStorage::new_with_padding(color, 3, 0.0),
         }
     }
 
@@ -57,4 +305,291 @@ impl MaterialStorage {
     pub fn new_light(color: [f32; 3]) -> MaterialStorage {
         MaterialStorage::new(color, MaterialKind::Light)
     }
+
+    /// `scattering` and `absorption` are the coefficients per unit length.
+    pub fn new_medium(
+        scattering: [f32; 3],
+        absorption: [f32; 3],
+        anisotropy: f32,
+    ) -> MaterialStorage {
+        MaterialStorage::new(
+            scattering,
+            MaterialKind::Medium {
+                absorption,
+                anisotropy,
+            },
+        )
+    }
+
+    /// Scatters isotropically inside. Only closed geometry with consistently
+    /// outward facing normals, such as spheres, has a well defined inside.
+    pub fn new_subsurface(
+        albedo: [f32; 3],
+        refractive_index: f32,
+        mean_free_path: [f32; 3],
+    ) -> MaterialStorage {
+        MaterialStorage::new(
+            albedo,
+            MaterialKind::Subsurface {
+                refractive_index,
+                mean_free_path,
+                anisotropy: 0.0,
+            },
+        )
+    }
+
+    pub fn new_anisotropic_metallic(
+        color: [f32; 3],
+        roughness_tangent: f32,
+        roughness_bitangent: f32,
+        rotation: f32,
+    ) -> MaterialStorage {
+        MaterialStorage::new(
+            color,
+            MaterialKind::AnisotropicMetallic {
+                roughness_tangent,
+                roughness_bitangent,
+                rotation,
+            },
+        )
+    }
+
+    pub fn new_cloth(
+        color: [f32; 3],
+        sheen_color: [f32; 3],
+        sheen_roughness: f32,
+    ) -> MaterialStorage {
+        MaterialStorage::new(
+            color,
+            MaterialKind::Cloth {
+                sheen_color,
+                sheen_roughness,
+            },
+        )
+    }
+
+    pub fn new_conductor(eta: [f32; 3], k: [f32; 3], roughness: f32) -> MaterialStorage {
+        MaterialStorage::new([1.0; 3], MaterialKind::Conductor { eta, k, roughness })
+    }
+
+    pub fn new_conductor_preset(preset: ConductorPreset, roughness: f32) -> MaterialStorage {
+        let (eta, k) = preset.refractive_index();
+        MaterialStorage::new_conductor(eta, k, roughness)
+    }
+
+    pub fn new_measured(table: u32) -> MaterialStorage {
+        MaterialStorage::new([1.0; 3], MaterialKind::Measured { table })
+    }
+
+    /// `first` and `second` are material indices returned by
+    /// `Scene::add_material`. Normal and bump maps of the mix apply to
+    /// both materials unless they have their own, while cutouts come from
+    /// the alpha of the mix alone.
+    pub fn new_mix(first: u32, second: u32, factor: MixFactor) -> MaterialStorage {
+        MaterialStorage::new(
+            [1.0; 3],
+            MaterialKind::Mix {
+                first,
+                second,
+                factor,
+            },
+        )
+    }
+
+    pub fn is_light(&self) -> bool {
+        self.kind == 3
+    }
+
+    pub fn is_conductor(&self) -> bool {
+        self.kind == 8
+    }
+
+    /// Swaps the refractive index of a conductor for the one of the preset,
+    /// keeping its roughness and tint.
+    pub fn with_conductor_preset(mut self, preset: ConductorPreset) -> MaterialStorage {
+        (self.conductor_eta, self.conductor_k) = preset.refractive_index();
+        self
+    }
+
+    /// `texture` is the index of a `TextureStorage` in the scene's texture
+    /// list. The color of the material gets multiplied by the texture.
+    pub fn with_albedo_texture(mut self, texture: u32) -> MaterialStorage {
+        self.albedo_texture = texture as i32;
+        self
+    }
+
+    /// The fuzz of a metallic material, or both roughnesses of an
+    /// anisotropic one or a conductor, get multiplied by the red channel of the texture.
+    pub fn with_roughness_texture(mut self, texture: u32) -> MaterialStorage {
+        self.roughness_texture = texture as i32;
+        self
+    }
+
+    /// The color emitted by a light gets multiplied by the texture.
+    pub fn with_emission_texture(mut self, texture: u32) -> MaterialStorage {
+        self.emission_texture = texture as i32;
+        self
+    }
+
+    /// Perturbs the shading normal with a tangent space normal map in the
+    /// OpenGL convention, where green points up in the image.
+    pub fn with_normal_texture(mut self, texture: u32) -> MaterialStorage {
+        self.normal_texture = texture as i32;
+        self
+    }
+
+    /// Perturbs the shading normal as if the surface was displaced along it
+    /// by the red channel of the texture times `strength`.
+    pub fn with_bump_texture(mut self, texture: u32, strength: f32) -> MaterialStorage {
+        self.bump_texture = texture as i32;
+        self.bump_strength = strength;
+        self
+    }
+
+    /// Rays pass through the surface with a probability of `1.0 - alpha`.
+    pub fn with_alpha(mut self, alpha: f32) -> MaterialStorage {
+        self.alpha = alpha;
+        self
+    }
+
+    /// The alpha of the material gets multiplied by the red channel of the
+    /// texture, so a black and white mask cuts out the black parts.
+    pub fn with_alpha_texture(mut self, texture: u32) -> MaterialStorage {
+        self.alpha_texture = texture as i32;
+        self
+    }
+
+    /// Lacquers the material with a clear layer with a refractive index of
+    /// 1.5. `strength` scales the reflectance of the layer and `roughness`
+    /// is its fuzz. Light not reflected by the layer reaches the material,
+    /// and what the material reflects passes the layer again on its way out.
+    /// On a dielectric the layer is its surface, so at full strength the
+    /// reflectance of the layer replaces the Fresnel reflection of the
+    /// dielectric.
+    pub fn with_clearcoat(mut self, strength: f32, roughness: f32) -> MaterialStorage {
+        self.clearcoat = strength;
+        self.clearcoat_roughness = roughness;
+        self
+    }
+
+    /// Covers the material with a film `thickness` nanometers thick, whose
+    /// reflections interfere into iridescent colors. The film is the top
+    /// of the clearcoat, so a full strength smooth clearcoat is added when
+    /// the material has none. On dielectrics and metals the film lies on the
+    /// material itself, whose complex refractive index shifts the
+    /// interference. Metals other than conductors get theirs from their
+    /// color. On a metal the film replaces the reflection of the metal in
+    /// proportion to the clearcoat, with the roughness of the clearcoat.
+    pub fn with_thin_film(mut self, thickness: f32, refractive_index: f32) -> MaterialStorage {
+        if self.clearcoat == 0.0 {
+            self.clearcoat = 1.0;
+        }
+        self.film_thickness = thickness;
+        self.film_refractive_index = refractive_index;
+        self
+    }
+
+    /// Fills a dielectric with the medium material at index `medium`, so
+    /// refracted rays travel through it, as in murky water.
+    pub fn with_interior_medium(mut self, medium: u32) -> MaterialStorage {
+        self.interior_medium = medium as i32;
+        self
+    }
+
+    /// Where dielectrics overlap, such as water filling a glass, only the
+    /// boundary of the one with the highest priority is rendered. Give the
+    /// container a higher priority than its contents.
+    pub fn with_priority(mut self, priority: u32) -> MaterialStorage {
+        self.priority = priority;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::mem::{offset_of, size_of};
+
+    /// The offsets of the `Material` struct in ray_tracer.wgsl, whose vec3s
+    /// are aligned to 16 bytes.
+    #[test]
+    fn layout_matches_the_shader() {
+        assert_eq!(size_of::<MaterialStorage>(), 192);
+        assert_eq!(offset_of!(MaterialStorage, kind), 12);
+        assert_eq!(offset_of!(MaterialStorage, priority), 72);
+        assert_eq!(offset_of!(MaterialStorage, absorption), 80);
+        assert_eq!(offset_of!(MaterialStorage, mean_free_path), 96);
+        assert_eq!(offset_of!(MaterialStorage, sheen_color), 128);
+        assert_eq!(offset_of!(MaterialStorage, conductor_eta), 144);
+        assert_eq!(offset_of!(MaterialStorage, conductor_k), 160);
+        assert_eq!(offset_of!(MaterialStorage, mix_materials), 176);
+        assert_eq!(offset_of!(MaterialStorage, mix_factor), 188);
+    }
+
+    #[test]
+    fn materials_start_untextured() {
+        let material = MaterialStorage::new_lambertian([1.0; 3]);
+        assert_eq!(material.albedo_texture, NO_TEXTURE);
+        assert_eq!(material.alpha_texture, NO_TEXTURE);
+        assert_eq!(material.interior_medium, NO_MEDIUM);
+        assert_eq!(material.alpha, 1.0);
+    }
+
+    const PRESETS: [ConductorPreset; 5] = [
+        ConductorPreset::Gold,
+        ConductorPreset::Silver,
+        ConductorPreset::Copper,
+        ConductorPreset::Aluminum,
+        ConductorPreset::Chrome,
+    ];
+
+    /// Reflectance at normal incidence per channel.
+    fn reflectance(preset: ConductorPreset) -> [f32; 3] {
+        let (eta, k) = preset.refractive_index();
+        [0, 1, 2].map(|i| {
+            let k2 = k[i] * k[i];
+            ((eta[i] - 1.0).powi(2) + k2) / ((eta[i] + 1.0).powi(2) + k2)
+        })
+    }
+
+    #[test]
+    fn presets_reflect_like_their_metals() {
+        let [r, g, b] = reflectance(ConductorPreset::Gold);
+        assert!(r > 0.9 && r > g && g > b && b < 0.5);
+
+        let [r, g, b] = reflectance(ConductorPreset::Copper);
+        assert!(r > 0.9 && r > g && g > b);
+
+        for preset in [ConductorPreset::Silver, ConductorPreset::Aluminum] {
+            assert!(
+                reflectance(preset).iter().all(|&r| r > 0.85),
+                "{:?}",
+                preset
+            );
+        }
+
+        let chrome = reflectance(ConductorPreset::Chrome);
+        assert!(chrome.iter().all(|&r| (r - chrome[1]).abs() < 0.05));
+    }
+
+    #[test]
+    fn presets_cycle_through_every_metal() {
+        let mut preset = ConductorPreset::Gold;
+        for expected in PRESETS.iter().cycle().skip(1).take(PRESETS.len()) {
+            preset = preset.next();
+            assert_eq!(preset.refractive_index(), expected.refractive_index());
+        }
+    }
+
+    #[test]
+    fn conductor_preset_keeps_the_roughness() {
+        let material = MaterialStorage::new_conductor_preset(ConductorPreset::Gold, 0.3)
+            .with_conductor_preset(ConductorPreset::Silver);
+        assert!(material.is_conductor());
+        assert_eq!(material.roughness_tangent, 0.3);
+        assert_eq!(
+            (material.conductor_eta, material.conductor_k),
+            ConductorPreset::Silver.refractive_index()
+        );
+    }
 }
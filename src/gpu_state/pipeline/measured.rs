@@ -0,0 +1,219 @@
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use wgpu::{
+    Device, Extent3d, Queue, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages,
+    TextureView, TextureViewDescriptor,
+};
+
+/// Number of samples of the half vector elevation in a MERL table.
+pub const THETA_HALF_SAMPLES: u32 = 90;
+/// Number of samples of the difference vector elevation in a MERL table.
+pub const THETA_DIFF_SAMPLES: u32 = 90;
+/// Number of samples of the difference vector azimuth in a MERL table. Only
+/// half a turn is stored, since the other half follows from reciprocity.
+pub const PHI_DIFF_SAMPLES: u32 = 180;
+
+/// Factors which turn the stored values into reflectances, per channel.
+const CHANNEL_SCALES: [f64; 3] = [1.0 / 1500.0, 1.15 / 1500.0, 1.66 / 1500.0];
+
+/// An isotropic BRDF measured by MERL, tabulated over the half and
+/// difference angles of Rusinkiewicz.
+pub struct MeasuredBrdf {
+    /// RGBA texels with the difference azimuth varying fastest, then the
+    /// difference elevation and then the half elevation.
+    texels: Vec<[f32; 4]>,
+}
+
+impl MeasuredBrdf {
+    /// Loads a MERL binary file, laid out as
+    ///
+    /// * the number of half elevation, difference elevation and difference
+    ///   azimuth samples as little endian `i32`s, which must be 90, 90 and
+    ///   180,
+    /// * every red value, then every green value and then every blue value
+    ///   as little endian `f64`s, with the difference azimuth varying
+    ///   fastest and the half elevation slowest.
+    pub fn load(path: &str) -> Result<MeasuredBrdf> {
+        let bytes = fs::read(path)?;
+        if bytes.len() < 12 {
+            return Err(Error::new(ErrorKind::InvalidData, "not a MERL BRDF"));
+        }
+
+        let read_i32 = |offset: usize| {
+            i32::from_le_bytes([
+                bytes[offset],
+                bytes[offset + 1],
+                bytes[offset + 2],
+                bytes[offset + 3],
+            ])
+        };
+        let dimensions = [read_i32(0), read_i32(4), read_i32(8)];
+        let expected = [THETA_HALF_SAMPLES, THETA_DIFF_SAMPLES, PHI_DIFF_SAMPLES].map(|n| n as i32);
+        if dimensions != expected {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "MERL BRDF does not have 90x90x180 samples",
+            ));
+        }
+
+        let samples = (THETA_HALF_SAMPLES * THETA_DIFF_SAMPLES * PHI_DIFF_SAMPLES) as usize;
+        let data = &bytes[12..];
+        if data.len() != 3 * 8 * samples {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "MERL BRDF size does not match its data",
+            ));
+        }
+
+        let values: Vec<f64> = data
+            .chunks_exact(8)
+            .map(|c| f64::from_le_bytes([c[0], c[1], c[2], c[3], c[4], c[5], c[6], c[7]]))
+            .collect();
+
+        // Directions which were not measured are stored as negative values.
+        let texels = (0..samples)
+            .map(|i| {
+                let channel = |c: usize| (values[c * samples + i] * CHANNEL_SCALES[c]).max(0.0);
+                [channel(0) as f32, channel(1) as f32, channel(2) as f32, 0.0]
+            })
+            .collect();
+
+        Ok(MeasuredBrdf { texels })
+    }
+
+    /// Uploads the tables into a single 3D texture, stacked along the half
+    /// elevation, so the table at `brdfs[i]` starts at depth `90 * i`.
+    /// Without tables a single empty texel is uploaded, since the shader
+    /// always needs a texture bound. Fails when the stacked tables are
+    /// deeper than the device supports, which is past 22 tables at the
+    /// default limit of 2048.
+    pub fn create_view(
+        brdfs: &[MeasuredBrdf],
+        device: &Device,
+        queue: &Queue,
+    ) -> Result<TextureView> {
+        let max_depth = device.limits().max_texture_dimension_3d;
+        let max_tables = max_depth / THETA_HALF_SAMPLES;
+        if brdfs.len() > max_tables as usize {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "{} measured BRDFs exceed the limit of {} for a 3D texture depth of {}",
+                    brdfs.len(),
+                    max_tables,
+                    max_depth
+                ),
+            ));
+        }
+
+        let (extent, texels) = if brdfs.is_empty() {
+            let extent = Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            };
+            (extent, vec![[0.0; 4]])
+        } else {
+            let extent = Extent3d {
+                width: PHI_DIFF_SAMPLES,
+                height: THETA_DIFF_SAMPLES,
+                depth_or_array_layers: THETA_HALF_SAMPLES * brdfs.len() as u32,
+            };
+            let texels = brdfs
+                .iter()
+                .flat_map(|brdf| brdf.texels.iter().copied())
+                .collect();
+            (extent, texels)
+        };
+
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some("Measured BRDFs"),
+            size: extent,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D3,
+            format: TextureFormat::Rgba32Float,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        queue.write_texture(
+            texture.as_image_copy(),
+            bytemuck::cast_slice(&texels),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(extent.width * 16),
+                rows_per_image: Some(extent.height),
+            },
+            extent,
+        );
+
+        Ok(texture.create_view(&TextureViewDescriptor::default()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpu_state::pipeline::temp_file::TempFile;
+
+    const SAMPLES: usize = (THETA_HALF_SAMPLES * THETA_DIFF_SAMPLES * PHI_DIFF_SAMPLES) as usize;
+
+    fn brdf_bytes(dimensions: [i32; 3], values: &[f64]) -> Vec<u8> {
+        let mut bytes: Vec<u8> = dimensions.iter().flat_map(|n| n.to_le_bytes()).collect();
+        bytes.extend(values.iter().flat_map(|v| v.to_le_bytes()));
+        bytes
+    }
+
+    #[test]
+    fn scales_every_channel() {
+        // Red holds the sample index, green twice that and blue is negative.
+        let values: Vec<f64> = (0..3 * SAMPLES)
+            .map(|i| match i / SAMPLES {
+                0 => (i % SAMPLES) as f64,
+                1 => 2.0 * (i % SAMPLES) as f64,
+                _ => -1.0,
+            })
+            .collect();
+        let file = TempFile::new("scales_every_channel", &brdf_bytes([90, 90, 180], &values));
+
+        let brdf = MeasuredBrdf::load(file.path()).unwrap();
+        assert_eq!(brdf.texels.len(), SAMPLES);
+        for i in [0, 1, 180, SAMPLES - 1] {
+            let expected = [
+                (i as f64 / 1500.0) as f32,
+                (2.0 * i as f64 * (1.15 / 1500.0)) as f32,
+                0.0,
+                0.0,
+            ];
+            assert_eq!(brdf.texels[i], expected);
+        }
+    }
+
+    #[test]
+    fn rejects_other_dimensions() {
+        let file = TempFile::new(
+            "rejects_other_dimensions",
+            &brdf_bytes([90, 90, 360], &[0.0; 3]),
+        );
+
+        let error = MeasuredBrdf::load(file.path()).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        for (name, bytes) in [
+            ("rejects_truncated_headers", vec![90, 0, 0, 0]),
+            (
+                "rejects_truncated_data",
+                brdf_bytes([90, 90, 180], &vec![0.0; 3 * SAMPLES - 1]),
+            ),
+        ] {
+            let file = TempFile::new(name, &bytes);
+
+            let error = MeasuredBrdf::load(file.path()).err().unwrap();
+            assert_eq!(error.kind(), ErrorKind::InvalidData, "{}", name);
+        }
+    }
+}
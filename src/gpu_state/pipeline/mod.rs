@@ -1,21 +1,52 @@
+mod adaptive;
+mod aov;
 mod camera;
+mod denoiser;
+mod display;
+mod filter;
 mod geometry;
 mod material;
+mod measured;
+mod output;
+mod readback;
+mod reprojection;
+mod scene;
+mod sheen;
+mod stop;
+#[cfg(test)]
+mod temp_file;
+mod texture;
 mod vertex;
+mod volume;
 
+use adaptive::AdaptiveSettings;
+use aov::Aov;
 use camera::Camera;
-use geometry::GeometryStorage;
-use material::MaterialStorage;
+use denoiser::Denoiser;
+use display::DisplaySettings;
+pub use filter::{FilterKind, PixelFilter};
+use image::ImageResult;
+pub use material::{ConductorPreset, MaterialStorage};
+use measured::MeasuredBrdf;
+pub use output::ExrPrecision;
 use rand::{thread_rng, Rng};
+use reprojection::Reprojection;
+pub use scene::Scene;
+use sheen::SheenAlbedo;
+use std::mem::size_of;
+use std::time::Instant;
+pub use stop::StopCriteria;
+use texture::{TextureArray, TextureStorage};
 use vertex::Vertex;
+use volume::DensityGrid;
 use wgpu::{
     util::{BufferInitDescriptor, DeviceExt},
     BindGroup, BlendState, Buffer, Color, ColorTargetState, ColorWrites, CommandEncoder,
     ComputePassDescriptor, ComputePipeline, ComputePipelineDescriptor, Device, Face, FragmentState,
     FrontFace, LoadOp, MultisampleState, Operations, PipelineLayoutDescriptor, PolygonMode,
-    PrimitiveState, PrimitiveTopology, RenderPassColorAttachment, RenderPassDescriptor,
-    RenderPipeline, RenderPipelineDescriptor, ShaderModuleDescriptor, ShaderSource,
-    SurfaceConfiguration, Texture, TextureView, VertexState,
+    PrimitiveState, PrimitiveTopology, Queue, RenderPassColorAttachment, RenderPassDescriptor,
+    RenderPipeline, RenderPipelineDescriptor, ShaderModuleDescriptor, ShaderSource, Texture,
+    TextureFormat, TextureView, VertexState,
 };
 use winit::dpi::PhysicalSize;
 
@@ -34,111 +65,50 @@ pub struct Pipeline {
     size: wgpu::Extent3d,
     camera: Camera,
     camera_buffer: Buffer,
-    objects_buffer: Buffer,
+    /// What the materials buffer holds, so single materials can be edited.
+    materials: Vec<MaterialStorage>,
+    materials_buffer: Buffer,
     sample_count_buffer: Buffer,
     vertex_buffer: Buffer,
     random_texture: Texture,
     random_bind_group: BindGroup,
     camera_bind_group: BindGroup,
-    compute1_bind_group1: BindGroup,
-    compute2_bind_group1: BindGroup,
-    compute1_bind_group2: BindGroup,
-    compute2_bind_group2: BindGroup,
+    accumulation_buffer: Buffer,
+    aov_buffer: Buffer,
+    moments_buffer: Buffer,
+    adaptive: AdaptiveSettings,
+    adaptive_buffer: Buffer,
+    filter: PixelFilter,
+    filter_buffer: Buffer,
+    accumulation_bind_group: BindGroup,
     compute_pipeline: ComputePipeline,
-    render_bind_group1: BindGroup,
-    render_bind_group2: BindGroup,
+    render_bind_group: BindGroup,
+    denoised_render_bind_groups: [BindGroup; 2],
+    denoiser: Denoiser,
+    denoised_buffers: [Buffer; 2],
+    reprojection: Reprojection,
     render_pipeline: RenderPipeline,
-    buffer_switch: bool,
+    screenshot_pipeline: RenderPipeline,
+    screenshot_format: TextureFormat,
+    scene_name: &'static str,
+    display: DisplaySettings,
+    display_buffer: Buffer,
     sample_count: u32,
+    /// When the sample count was last reset.
+    started: Instant,
 }
 
+/// Samples between the measurements of the noise for the stop criteria.
+const ERROR_CHECK_INTERVAL: u32 = 16;
+
 impl Pipeline {
     pub fn new(
         device: &Device,
-        config: &SurfaceConfiguration,
+        queue: &Queue,
+        format: TextureFormat,
         size: PhysicalSize<u32>,
+        scene: Scene,
     ) -> Pipeline {
-        let white_lambertian = MaterialStorage::new_lambertian([1.0, 1.0, 1.0]);
-        let gray_lambertian = MaterialStorage::new_lambertian([0.5, 0.5, 0.5]);
-        let red_lambertian = MaterialStorage::new_lambertian([1.0, 0.0, 0.0]);
-        let green_lambertian = MaterialStorage::new_lambertian([0.0, 1.0, 0.0]);
-        let white_glass = MaterialStorage::new_dielectric([1.0, 1.0, 1.0], 1.5);
-        let white_light = MaterialStorage::new_light([2.0, 2.0, 2.0]);
-
-        let objects = &[
-            // Light surface
-            GeometryStorage::new_quad(
-                [3.5, -0.5, 1.0],
-                [0.0, 1.0, 0.0],
-                [1.0, 0.0, 0.0],
-                white_light,
-            ),
-            // Glass sphere
-            GeometryStorage::new_sphere([4.0, -0.5, -0.75], 0.25, white_glass),
-            GeometryStorage::new_quad(
-                [4.0, 0.25, -1.0],
-                [0.0, 0.0, 0.75],
-                [0.5, -0.25, 0.0],
-                gray_lambertian,
-            ),
-            // Grey box
-            GeometryStorage::new_quad(
-                [4.0, 0.25, -1.0],
-                [0.0, 0.0, 0.75],
-                [0.25, 0.5, 0.0],
-                gray_lambertian,
-            ),
-            GeometryStorage::new_quad(
-                [4.0, 0.25, -0.25],
-                [0.5, -0.25, 0.0],
-                [0.25, 0.5, 0.0],
-                gray_lambertian,
-            ),
-            GeometryStorage::new_quad(
-                [4.25, 0.75, -1.0],
-                [0.0, 0.0, 0.75],
-                [0.5, -0.25, 0.0],
-                gray_lambertian,
-            ),
-            GeometryStorage::new_quad(
-                [4.5, 0.0, -1.0],
-                [0.0, 0.0, 0.75],
-                [0.25, 0.5, 0.0],
-                gray_lambertian,
-            ),
-            // Enclosing multi-colored box
-            GeometryStorage::new_quad(
-                [3.0, -1.0, -1.0],
-                [2.0, 0.0, 0.0],
-                [0.0, 2.0, 0.0],
-                white_lambertian,
-            ),
-            GeometryStorage::new_quad(
-                [3.0, -1.0, 1.0],
-                [2.0, 0.0, 0.0],
-                [0.0, 2.0, 0.0],
-                white_lambertian,
-            ),
-            GeometryStorage::new_quad(
-                [5.0, -1.0, -1.0],
-                [0.0, 0.0, 2.0],
-                [0.0, 2.0, 0.0],
-                white_lambertian,
-            ),
-            GeometryStorage::new_quad(
-                [3.0, 1.0, -1.0],
-                [2.0, 0.0, 0.0],
-                [0.0, 0.0, 2.0],
-                red_lambertian,
-            ),
-            GeometryStorage::new_quad(
-                [3.0, -1.0, -1.0],
-                [0.0, 0.0, 2.0],
-                [2.0, 0.0, 0.0],
-                green_lambertian,
-            ),
-        ];
-
         let vertex_buffer = device.create_buffer_init(&BufferInitDescriptor {
             label: Some("Camera Buffer"),
             contents: bytemuck::cast_slice(RECTANGLE_VERTICES),
@@ -151,52 +121,44 @@ impl Pipeline {
             depth_or_array_layers: 1,
         };
 
-        let texture1 = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Texture"),
-            size,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8Unorm,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING
-                | wgpu::TextureUsages::STORAGE_BINDING
-                | wgpu::TextureUsages::COPY_DST,
-            view_formats: &[],
+        // Running sum of the samples of every pixel, in rgb, and the number
+        // of samples in w. The display pass divides one by the other.
+        let accumulation_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Accumulation Buffer"),
+            size: (size.width * size.height) as wgpu::BufferAddress * 16,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
         });
 
-        let view1 = texture1.create_view(&wgpu::TextureViewDescriptor::default());
-        let sampler1 = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
-            ..Default::default()
+        let aov_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("AOV Buffer"),
+            size: (size.width * size.height) as wgpu::BufferAddress * size_of::<Aov>() as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
         });
 
-        let texture2 = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Texture"),
-            size,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8Unorm,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING
-                | wgpu::TextureUsages::STORAGE_BINDING
-                | wgpu::TextureUsages::COPY_DST,
-            view_formats: &[],
+        // Sums of the luminance of the samples of every pixel since the
+        // camera last moved in x, of its square in y and their number in z,
+        // from which adaptive sampling estimates the noise.
+        let moments_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Moments Buffer"),
+            size: (size.width * size.height) as wgpu::BufferAddress * 16,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
         });
 
-        let view2 = texture2.create_view(&wgpu::TextureViewDescriptor::default());
-        let sampler2 = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
-            ..Default::default()
+        let adaptive = AdaptiveSettings::default();
+        let adaptive_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Adaptive Settings Buffer"),
+            contents: bytemuck::cast_slice(&[adaptive]),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
+        });
+
+        let display = DisplaySettings::new(size.width, size.height, !format.is_srgb());
+        let display_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Display Settings Buffer"),
+            contents: bytemuck::cast_slice(&[display]),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
         });
 
         let camera = Camera::default();
@@ -209,13 +171,72 @@ impl Pipeline {
 
         let objects_buffer = device.create_buffer_init(&BufferInitDescriptor {
             label: Some("Objects Buffer Descriptor"),
-            contents: bytemuck::cast_slice(objects),
+            contents: bytemuck::cast_slice(&scene.objects),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
+        });
+
+        let materials_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Materials Buffer Descriptor"),
+            contents: bytemuck::cast_slice(&scene.materials),
             usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
         });
 
+        let density_grid = scene.volume.as_ref().map(|volume| {
+            DensityGrid::load(volume.path)
+                .unwrap_or_else(|e| panic!("Failed to load density grid {}: {}", volume.path, e))
+        });
+        let max_density = density_grid.as_ref().map_or(0.0, |grid| grid.max_density());
+        let density_view = DensityGrid::create_view(density_grid.as_ref(), device, queue)
+            .unwrap_or_else(|e| panic!("Failed to upload density grid: {}", e));
+
+        let measured_brdfs: Vec<MeasuredBrdf> = scene
+            .measured_brdf_paths
+            .iter()
+            .map(|path| {
+                MeasuredBrdf::load(path)
+                    .unwrap_or_else(|e| panic!("Failed to load measured BRDF {}: {}", path, e))
+            })
+            .collect();
+        let measured_view = MeasuredBrdf::create_view(&measured_brdfs, device, queue)
+            .unwrap_or_else(|e| panic!("Failed to upload measured BRDFs: {}", e));
+
+        let scene_settings_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Scene Settings Buffer Descriptor"),
+            contents: bytemuck::cast_slice(&[scene.settings(max_density)]),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
+        });
+
         let sample_count_buffer = device.create_buffer_init(&BufferInitDescriptor {
             label: Some("Sample Count Buffer Descriptor"),
             contents: bytemuck::cast_slice(&[0u32]),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
+        });
+
+        let filter = PixelFilter::default();
+        let filter_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Filter Buffer"),
+            contents: bytemuck::cast_slice(&[filter.into_uniform()]),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
+        });
+
+        let sheen_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Sheen Albedo Buffer"),
+            contents: bytemuck::cast_slice(&[SheenAlbedo::tabulate()]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        let texture_array = TextureArray::new(device, queue, &scene.texture_paths);
+
+        // A storage buffer cannot be empty, so an unused texture is uploaded
+        // when the scene has none.
+        let unused_texture = [TextureStorage::new_image(0)];
+        let textures_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Textures Buffer Descriptor"),
+            contents: if scene.textures.is_empty() {
+                bytemuck::cast_slice(&unused_texture)
+            } else {
+                bytemuck::cast_slice(&scene.textures)
+            },
             usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
         });
 
@@ -244,70 +265,74 @@ impl Pipeline {
             source: ShaderSource::Wgsl(include_str!("ray_tracer.wgsl").into()),
         });
 
-        let compute1_bind_group_layout =
+        let accumulation_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::StorageTexture {
-                        access: wgpu::StorageTextureAccess::WriteOnly,
-                        format: wgpu::TextureFormat::Rgba8Unorm,
-                        view_dimension: wgpu::TextureViewDimension::D2,
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
                     },
-                    count: None,
-                }],
-                label: Some("compute_bind_group_layout"),
-            });
-
-        let compute1_bind_group1 = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &compute1_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::TextureView(&view1), // CHANGED!
-            }],
-            label: Some("compute_bind_group"),
-        });
-
-        let compute1_bind_group2 = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &compute1_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::TextureView(&view2), // CHANGED!
-            }],
-            label: Some("compute_bind_group"),
-        });
-
-        let compute2_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::StorageTexture {
-                        access: wgpu::StorageTextureAccess::ReadOnly,
-                        format: wgpu::TextureFormat::Rgba8Unorm,
-                        view_dimension: wgpu::TextureViewDimension::D2,
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
                     },
-                    count: None,
-                }],
-                label: Some("compute_bind_group_layout"),
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+                label: Some("accumulation_bind_group_layout"),
             });
 
-        let compute2_bind_group2 = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &compute2_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::TextureView(&view2), // CHANGED!
-            }],
-            label: Some("compute_bind_group"),
-        });
-
-        let compute2_bind_group1 = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &compute2_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::TextureView(&view1), // CHANGED!
-            }],
-            label: Some("compute_bind_group"),
+        let accumulation_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &accumulation_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: accumulation_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: aov_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: moments_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: adaptive_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("accumulation_bind_group"),
         });
 
         let camera_bind_group_layout =
@@ -333,9 +358,47 @@ impl Pipeline {
                         },
                         count: None,
                     },
+                    // A uniform rather than a storage buffer, since the
+                    // compute stage can only have 8 of those.
                     wgpu::BindGroupLayoutEntry {
                         binding: 2,
                         visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2Array,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 5,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 6,
+                        visibility: wgpu::ShaderStages::COMPUTE,
                         ty: wgpu::BindingType::Buffer {
                             ty: wgpu::BufferBindingType::Storage { read_only: true },
                             has_dynamic_offset: false,
@@ -343,6 +406,56 @@ impl Pipeline {
                         },
                         count: None,
                     },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 7,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 8,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D3,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 9,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D3,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 10,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 11,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
                 ],
                 label: Some("camera_bind_group_layout"),
             });
@@ -362,6 +475,42 @@ impl Pipeline {
                     binding: 2,
                     resource: sample_count_buffer.as_entire_binding(),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(texture_array.view()),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::Sampler(texture_array.sampler()),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: textures_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: materials_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: scene_settings_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 8,
+                    resource: wgpu::BindingResource::TextureView(&density_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 9,
+                    resource: wgpu::BindingResource::TextureView(&measured_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 10,
+                    resource: filter_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 11,
+                    resource: sheen_buffer.as_entire_binding(),
+                },
             ],
             label: Some("camera_bind_group"),
         });
@@ -390,13 +539,20 @@ impl Pipeline {
             label: Some("random_bind_group"),
         });
 
+        let reprojection = Reprojection::new(
+            device,
+            &accumulation_buffer,
+            &aov_buffer,
+            camera.into_uniform(),
+        );
+
         let compute_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
             label: Some("Compute Pipeline Layout"),
             bind_group_layouts: &[
-                &compute1_bind_group_layout,
+                &accumulation_bind_group_layout,
                 &camera_bind_group_layout,
                 &random_bind_group_layout,
-                &compute2_bind_group_layout,
+                reprojection.bind_group_layout(),
             ],
             push_constant_ranges: &[],
         });
@@ -414,54 +570,96 @@ impl Pipeline {
                     wgpu::BindGroupLayoutEntry {
                         binding: 0,
                         visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
                         },
                         count: None,
                     },
                     wgpu::BindGroupLayoutEntry {
                         binding: 1,
                         visibility: wgpu::ShaderStages::FRAGMENT,
-                        // This should match the filterable field of the
-                        // corresponding Texture entry above.
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
                         count: None,
                     },
                 ],
-                label: Some("texture_bind_group_layout"),
+                label: Some("render_bind_group_layout"),
             });
 
-        let render_bind_group1 = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &render_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&view1), // CHANGED!
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&sampler1), // CHANGED!
-                },
-            ],
-            label: Some("render_bind_group"),
-        });
+        // The display pass shows either the accumulation or the output of
+        // the denoiser.
+        let create_render_bind_group = |image: &Buffer| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &render_bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: image.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: display_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: aov_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: accumulation_buffer.as_entire_binding(),
+                    },
+                ],
+                label: Some("render_bind_group"),
+            })
+        };
 
-        let render_bind_group2 = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &render_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&view2), // CHANGED!
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&sampler2), // CHANGED!
-                },
-            ],
-            label: Some("render_bind_group"),
+        let denoised_buffers = [0, 1].map(|_| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Denoised Buffer"),
+                size: accumulation_buffer.size(),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            })
         });
+        let denoiser = Denoiser::new(
+            device,
+            size,
+            &accumulation_buffer,
+            &aov_buffer,
+            &denoised_buffers,
+        );
+        denoiser.update(queue);
+
+        let render_bind_group = create_render_bind_group(&accumulation_buffer);
+        let denoised_render_bind_groups = [
+            create_render_bind_group(&denoised_buffers[0]),
+            create_render_bind_group(&denoised_buffers[1]),
+        ];
 
         let render_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
             label: Some("Render Pipeline Layout"),
@@ -469,118 +667,283 @@ impl Pipeline {
             push_constant_ranges: &[],
         });
 
-        let render_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
-            label: Some("Simple Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: VertexState {
-                module: &shader,
-                entry_point: "vs_main",     // 1.
-                buffers: &[Vertex::desc()], // 2.
-            },
-            fragment: Some(FragmentState {
-                // 3.
-                module: &shader,
-                entry_point: "fs_main",
-                targets: &[Some(ColorTargetState {
-                    // 4.
-                    format: config.format,
-                    blend: Some(BlendState::REPLACE),
-                    write_mask: ColorWrites::ALL,
-                })],
-            }),
-            primitive: PrimitiveState {
-                topology: PrimitiveTopology::TriangleList, // 1.
-                strip_index_format: None,
-                front_face: FrontFace::Ccw, // 2.
-                cull_mode: Some(Face::Back),
-                // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
-                polygon_mode: PolygonMode::Fill,
-                // Requires Features::DEPTH_CLIP_CONTROL
-                unclipped_depth: false,
-                // Requires Features::CONSERVATIVE_RASTERIZATION
-                conservative: false,
-            },
-            depth_stencil: None, // 1.
-            multisample: MultisampleState {
-                count: 1,                         // 2.
-                mask: !0,                         // 3.
-                alpha_to_coverage_enabled: false, // 4.
-            },
-            multiview: None, // 5.
-        });
+        let create_display_pipeline = |format: TextureFormat| {
+            device.create_render_pipeline(&RenderPipelineDescriptor {
+                label: Some("Simple Render Pipeline"),
+                layout: Some(&render_pipeline_layout),
+                vertex: VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",     // 1.
+                    buffers: &[Vertex::desc()], // 2.
+                },
+                fragment: Some(FragmentState {
+                    // 3.
+                    module: &shader,
+                    entry_point: "fs_main",
+                    targets: &[Some(ColorTargetState {
+                        // 4.
+                        format,
+                        blend: Some(BlendState::REPLACE),
+                        write_mask: ColorWrites::ALL,
+                    })],
+                }),
+                primitive: PrimitiveState {
+                    topology: PrimitiveTopology::TriangleList, // 1.
+                    strip_index_format: None,
+                    front_face: FrontFace::Ccw, // 2.
+                    cull_mode: Some(Face::Back),
+                    // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
+                    polygon_mode: PolygonMode::Fill,
+                    // Requires Features::DEPTH_CLIP_CONTROL
+                    unclipped_depth: false,
+                    // Requires Features::CONSERVATIVE_RASTERIZATION
+                    conservative: false,
+                },
+                depth_stencil: None, // 1.
+                multisample: MultisampleState {
+                    count: 1,                         // 2.
+                    mask: !0,                         // 3.
+                    alpha_to_coverage_enabled: false, // 4.
+                },
+                multiview: None, // 5.
+            })
+        };
+
+        let render_pipeline = create_display_pipeline(format);
+        // Screenshots go through an RGBA8 texture which encodes to sRGB the
+        // same way the surface does, since the display pass only encodes
+        // when the surface does not.
+        let screenshot_format = if format.is_srgb() {
+            TextureFormat::Rgba8UnormSrgb
+        } else {
+            TextureFormat::Rgba8Unorm
+        };
+        let screenshot_pipeline = create_display_pipeline(screenshot_format);
 
         Pipeline {
             size,
             camera,
             vertex_buffer,
             camera_buffer,
-            objects_buffer,
+            materials: scene.materials,
+            materials_buffer,
             sample_count_buffer,
             random_texture,
             random_bind_group,
             camera_bind_group,
-            compute1_bind_group1,
-            compute2_bind_group1,
-            compute1_bind_group2,
-            compute2_bind_group2,
+            accumulation_buffer,
+            aov_buffer,
+            moments_buffer,
+            adaptive,
+            adaptive_buffer,
+            filter,
+            filter_buffer,
+            accumulation_bind_group,
             compute_pipeline,
-            render_bind_group1,
-            render_bind_group2,
+            render_bind_group,
+            denoised_render_bind_groups,
+            denoiser,
+            denoised_buffers,
+            reprojection,
             render_pipeline,
-            buffer_switch: true,
+            screenshot_pipeline,
+            screenshot_format,
+            scene_name: scene.name,
+            display,
+            display_buffer,
             sample_count: 0,
+            started: Instant::now(),
         }
     }
 
-    pub fn render<'a>(&'a self, encoder: &mut CommandEncoder, view: &TextureView) {
-        {
-            let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
-                label: Some("Compute Pass"),
-            });
-            if self.buffer_switch {
-                compute_pass.set_bind_group(0, &self.compute1_bind_group1, &[]);
-            } else {
-                compute_pass.set_bind_group(0, &self.compute1_bind_group2, &[]);
-            }
-            compute_pass.set_bind_group(1, &self.camera_bind_group, &[]);
-            compute_pass.set_bind_group(2, &self.random_bind_group, &[]);
-            if self.buffer_switch {
-                compute_pass.set_bind_group(3, &self.compute2_bind_group2, &[]);
-            } else {
-                compute_pass.set_bind_group(3, &self.compute2_bind_group1, &[]);
-            }
-            compute_pass.set_pipeline(&self.compute_pipeline);
-            compute_pass.dispatch_workgroups(self.size.width, self.size.height, 1);
+    pub fn render(&self, encoder: &mut CommandEncoder, view: &TextureView) {
+        self.trace(encoder);
+        self.present(encoder, view);
+    }
+
+    /// Displays the samples without adding any.
+    pub fn present(&self, encoder: &mut CommandEncoder, view: &TextureView) {
+        if self.denoiser.enabled() {
+            self.denoiser.run(encoder);
         }
+        self.display_pass(encoder, view, &self.render_pipeline);
+    }
 
-        {
-            let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[Some(RenderPassColorAttachment {
-                    view: view,
-                    resolve_target: None,
-                    ops: Operations {
-                        load: LoadOp::Clear(Color {
-                            r: 0.0,
-                            g: 0.0,
-                            b: 0.0,
-                            a: 1.0,
-                        }),
-                        store: true,
-                    },
-                })],
-                depth_stencil_attachment: None,
+    /// Adds a sample to every pixel.
+    pub fn trace(&self, encoder: &mut CommandEncoder) {
+        self.reprojection
+            .copy_history(encoder, &self.accumulation_buffer, &self.aov_buffer);
+
+        let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("Compute Pass"),
+        });
+        compute_pass.set_bind_group(0, &self.accumulation_bind_group, &[]);
+        compute_pass.set_bind_group(1, &self.camera_bind_group, &[]);
+        compute_pass.set_bind_group(2, &self.random_bind_group, &[]);
+        compute_pass.set_bind_group(3, self.reprojection.bind_group(), &[]);
+        compute_pass.set_pipeline(&self.compute_pipeline);
+        compute_pass.dispatch_workgroups(self.size.width, self.size.height, 1);
+    }
+
+    fn display_pass(
+        &self,
+        encoder: &mut CommandEncoder,
+        view: &TextureView,
+        render_pipeline: &RenderPipeline,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Render Pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Clear(Color {
+                        r: 0.0,
+                        g: 0.0,
+                        b: 0.0,
+                        a: 1.0,
+                    }),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        let render_bind_group = if self.denoiser.enabled() {
+            &self.denoised_render_bind_groups[self.denoiser.output_index()]
+        } else {
+            &self.render_bind_group
+        };
+        render_pass.set_bind_group(0, render_bind_group, &[]);
+        render_pass.set_pipeline(render_pipeline);
+        render_pass.draw(0..NUM_VERTICES, 0..1);
+    }
+
+    /// Saves the image as it is displayed to a PNG file in the working
+    /// directory, named after the scene and the sample count, and returns
+    /// the file name.
+    pub fn save_screenshot(&self, device: &Device, queue: &Queue) -> ImageResult<String> {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Screenshot Texture"),
+            size: self.size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: self.screenshot_format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Screenshot Encoder"),
+        });
+        self.display_pass(&mut encoder, &view, &self.screenshot_pipeline);
+        queue.submit([encoder.finish()]);
+
+        let pixels = readback::read_texture(device, queue, &texture, 4);
+        let path = format!("{}_{}spp.png", self.scene_name, self.sample_count);
+        image::save_buffer(
+            &path,
+            &pixels,
+            self.size.width,
+            self.size.height,
+            image::ColorType::Rgba8,
+        )?;
+        Ok(path)
+    }
+
+    /// Saves the linear radiance of every pixel, before exposure and tone
+    /// mapping, to an OpenEXR file at `path`, or in the working directory
+    /// named after the scene and the sample count without one, and returns
+    /// the file name.
+    pub fn save_exr(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        path: Option<&str>,
+        precision: ExrPrecision,
+    ) -> exr::error::Result<String> {
+        let path = match path {
+            Some(path) => path.to_string(),
+            None => format!("{}_{}spp.exr", self.scene_name, self.sample_count),
+        };
+        self.save_exr_to(device, queue, &path, precision)?;
+        Ok(path)
+    }
+
+    pub fn save_exr_to(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        path: &str,
+        precision: ExrPrecision,
+    ) -> exr::error::Result<()> {
+        let bytes = readback::read_buffer(device, queue, &self.accumulation_buffer);
+        let sums: &[[f32; 4]] = bytemuck::cast_slice(&bytes);
+
+        let mut channels = output::radiance_channels(sums, None);
+
+        if self.denoiser.enabled() {
+            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Denoise Encoder"),
             });
+            self.denoiser.run(&mut encoder);
+            queue.submit([encoder.finish()]);
 
-            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-            if self.buffer_switch {
-                render_pass.set_bind_group(0, &self.render_bind_group1, &[]);
-            } else {
-                render_pass.set_bind_group(0, &self.render_bind_group2, &[])
-            }
-            render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.draw(0..NUM_VERTICES, 0..1);
+            let output = &self.denoised_buffers[self.denoiser.output_index()];
+            let bytes = readback::read_buffer(device, queue, output);
+            channels.extend(output::radiance_channels(
+                bytemuck::cast_slice(&bytes),
+                Some("denoised"),
+            ));
         }
+
+        let bytes = readback::read_buffer(device, queue, &self.aov_buffer);
+        let sample_counts: Vec<f32> = sums.iter().map(|sum| sum[3]).collect();
+        channels.extend(aov::exr_channels(
+            bytemuck::cast_slice(&bytes),
+            &sample_counts,
+        ));
+        channels.push(output::ExrChannel::new("sampleCount", sample_counts));
+
+        output::write_exr(path, self.size.width, self.size.height, channels, precision)
+    }
+
+    /// Whether the samples since the camera last moved meet any of
+    /// `criteria`, which is printed when they do. The noise is only
+    /// measured every few samples, since that reads the moments back.
+    pub fn finished(&self, device: &Device, queue: &Queue, criteria: &StopCriteria) -> bool {
+        let elapsed = self.started.elapsed();
+        let reason = if criteria
+            .samples()
+            .is_some_and(|samples| self.sample_count >= samples)
+        {
+            "reached the sample count".to_string()
+        } else if criteria.time().is_some_and(|time| elapsed >= time) {
+            "ran out of time".to_string()
+        } else if let Some(threshold) = criteria
+            .error()
+            .filter(|_| self.sample_count % ERROR_CHECK_INTERVAL == 0)
+        {
+            let bytes = readback::read_buffer(device, queue, &self.moments_buffer);
+            let error = stop::mean_relative_error(bytemuck::cast_slice(&bytes));
+            if error >= threshold {
+                return false;
+            }
+            format!("mean relative error {:.4}", error)
+        } else {
+            return false;
+        };
+
+        println!(
+            "Finished after {} samples in {:.1} s: {}",
+            self.sample_count,
+            elapsed.as_secs_f32(),
+            reason
+        );
+        true
     }
 
     pub fn camera(&mut self) -> &mut Camera {
@@ -595,6 +958,163 @@ impl Pipeline {
         );
     }
 
+    pub fn materials(&self) -> &[MaterialStorage] {
+        &self.materials
+    }
+
+    /// Replaces the material at `index` without touching the objects which
+    /// use it. The samples so far show the old material, so they restart.
+    pub fn update_material(&mut self, queue: &wgpu::Queue, index: u32, material: MaterialStorage) {
+        assert!(
+            (index as usize) < self.materials.len(),
+            "Material index {} is out of range for {} materials",
+            index,
+            self.materials.len()
+        );
+        self.materials[index as usize] = material;
+        queue.write_buffer(
+            &self.materials_buffer,
+            (index as usize * size_of::<MaterialStorage>()) as wgpu::BufferAddress,
+            bytemuck::cast_slice(&[material]),
+        );
+        self.reprojection.discard(queue);
+        self.reset_sample_count(queue);
+    }
+
+    /// Brightens the image by `stops`, without touching the samples.
+    pub fn adjust_exposure(&mut self, queue: &wgpu::Queue, stops: f32) {
+        self.display.set_exposure(self.display.exposure() + stops);
+        self.update_display(queue);
+        println!("Exposure: {:+.1} EV", self.display.exposure());
+    }
+
+    pub fn cycle_tone_mapping(&mut self, queue: &wgpu::Queue) {
+        self.display
+            .set_tone_mapping(self.display.tone_mapping().next());
+        self.update_display(queue);
+        println!("Tone mapping: {:?}", self.display.tone_mapping());
+    }
+
+    /// Switches between the image and its auxiliary outputs.
+    pub fn cycle_view(&mut self, queue: &wgpu::Queue) {
+        self.display.set_view(self.display.view().next());
+        self.update_display(queue);
+        println!("View: {:?}", self.display.view());
+    }
+
+    pub fn toggle_denoiser(&mut self) {
+        self.set_denoiser_enabled(!self.denoiser.enabled());
+    }
+
+    /// The denoiser filters what is displayed and adds a denoised layer to
+    /// OpenEXR files.
+    pub fn set_denoiser_enabled(&mut self, enabled: bool) {
+        self.denoiser.set_enabled(enabled);
+        println!(
+            "Denoiser: {}",
+            if self.denoiser.enabled() { "on" } else { "off" }
+        );
+    }
+
+    /// Adds `change` iterations to the denoiser, which blurs over twice the
+    /// distance with every iteration.
+    pub fn adjust_denoiser_iterations(&mut self, queue: &wgpu::Queue, change: i32) {
+        let iterations = self.denoiser.iterations() as i32 + change;
+        self.denoiser.set_iterations(iterations.max(1) as u32);
+        self.denoiser.update(queue);
+        println!("Denoiser iterations: {}", self.denoiser.iterations());
+    }
+
+    /// Multiplies how different the colors the denoiser blends can be.
+    pub fn scale_denoiser_color_sigma(&mut self, queue: &wgpu::Queue, factor: f32) {
+        self.denoiser
+            .set_color_sigma(self.denoiser.color_sigma() * factor);
+        self.denoiser.update(queue);
+        println!("Denoiser color sigma: {}", self.denoiser.color_sigma());
+    }
+
+    pub fn toggle_reprojection(&mut self) {
+        self.reprojection.set_enabled(!self.reprojection.enabled());
+        println!(
+            "Reprojection: {}",
+            if self.reprojection.enabled() {
+                "on"
+            } else {
+                "off"
+            }
+        );
+    }
+
+    pub fn toggle_adaptive_sampling(&mut self, queue: &wgpu::Queue) {
+        self.set_adaptive_sampling(queue, !self.adaptive.enabled());
+    }
+
+    /// Adaptive sampling stops tracing the pixels whose noise is below the
+    /// threshold.
+    pub fn set_adaptive_sampling(&mut self, queue: &wgpu::Queue, enabled: bool) {
+        self.adaptive.set_enabled(enabled);
+        self.update_adaptive(queue);
+        println!(
+            "Adaptive sampling: {}",
+            if self.adaptive.enabled() { "on" } else { "off" }
+        );
+    }
+
+    /// Sets the relative standard error below which adaptive sampling stops
+    /// tracing a pixel.
+    pub fn set_noise_threshold(&mut self, queue: &wgpu::Queue, threshold: f32) {
+        self.adaptive.set_threshold(threshold);
+        self.update_adaptive(queue);
+        println!("Noise threshold: {}", self.adaptive.threshold());
+    }
+
+    pub fn scale_noise_threshold(&mut self, queue: &wgpu::Queue, factor: f32) {
+        self.set_noise_threshold(queue, self.adaptive.threshold() * factor);
+    }
+
+    /// Switches to the next reconstruction filter at its usual radius.
+    pub fn cycle_filter(&mut self, queue: &wgpu::Queue) {
+        self.set_filter(queue, PixelFilter::new(self.filter.kind().next()));
+    }
+
+    /// Widens the reconstruction filter by `change` pixels.
+    pub fn adjust_filter_radius(&mut self, queue: &wgpu::Queue, change: f32) {
+        let radius = (self.filter.radius() + change).max(0.25);
+        self.set_filter(queue, self.filter.with_radius(radius));
+    }
+
+    /// Restarts the samples, which were filtered with the previous filter.
+    pub fn set_filter(&mut self, queue: &wgpu::Queue, filter: PixelFilter) {
+        self.filter = filter;
+        queue.write_buffer(
+            &self.filter_buffer,
+            0,
+            bytemuck::cast_slice(&[self.filter.into_uniform()]),
+        );
+        self.reset_sample_count(queue);
+        println!(
+            "Filter: {:?} with radius {}",
+            self.filter.kind(),
+            self.filter.radius()
+        );
+    }
+
+    fn update_adaptive(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(
+            &self.adaptive_buffer,
+            0,
+            bytemuck::cast_slice(&[self.adaptive]),
+        );
+    }
+
+    fn update_display(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(
+            &self.display_buffer,
+            0,
+            bytemuck::cast_slice(&[self.display]),
+        );
+    }
+
     pub fn put_random_texture(&self, queue: &wgpu::Queue) {
         let mut data = vec![0u32; (self.size.width * self.size.height) as usize];
         thread_rng().fill(&mut data[..]);
@@ -610,26 +1130,41 @@ impl Pipeline {
         );
     }
 
-    pub fn switch_buffer(&mut self) {
-        self.buffer_switch = !self.buffer_switch;
+    /// Restarts the sample count after the camera moved. With reprojection
+    /// the pixels which still see the same surfaces keep their samples.
+    pub fn camera_moved(&mut self, queue: &wgpu::Queue) {
+        self.reprojection.camera_moved(queue, self.sample_count > 0);
+        self.reset_sample_count(queue);
+    }
+
+    /// Has to be called after every frame is submitted.
+    pub fn finish_frame(&mut self, queue: &wgpu::Queue) {
+        self.reprojection
+            .finish_frame(queue, self.camera.into_uniform());
+        self.increment_sample_count(queue);
     }
 
-    pub fn reset_sample_count(&mut self, queue: &wgpu::Queue) {
+    fn reset_sample_count(&mut self, queue: &wgpu::Queue) {
         self.sample_count = 0;
+        self.started = Instant::now();
         queue.write_buffer(
             &self.sample_count_buffer,
             0,
             bytemuck::cast_slice(&[self.sample_count]),
         );
+        self.display.set_sample_count(self.sample_count);
+        self.update_display(queue);
     }
 
-    pub fn increment_sample_count(&mut self, queue: &wgpu::Queue) {
+    fn increment_sample_count(&mut self, queue: &wgpu::Queue) {
         self.sample_count += 1;
         queue.write_buffer(
             &self.sample_count_buffer,
             0,
             bytemuck::cast_slice(&[self.sample_count]),
         );
+        self.display.set_sample_count(self.sample_count);
+        self.update_display(queue);
         if self.sample_count % 100 == 0 {
             println!("{}", self.sample_count);
         }
@@ -0,0 +1,74 @@
+use exr::prelude::{f16, AnyChannel, AnyChannels, Encoding, FlatSamples, Image, SmallVec};
+use exr::prelude::{Result, WritableImage};
+
+/// Precision of the samples in an OpenEXR file.
+#[derive(Copy, Clone, Debug)]
+pub enum ExrPrecision {
+    /// 16 bit floats, which are enough for final images at half the size.
+    Half,
+    Float,
+}
+
+/// One channel of an OpenEXR file, with a sample per pixel in rows from top
+/// to bottom. Channels of a layer are named `layer.R`, `layer.G` and so on,
+/// the beauty channels have no layer.
+pub struct ExrChannel {
+    pub name: String,
+    pub samples: Vec<f32>,
+}
+
+impl ExrChannel {
+    pub fn new(name: impl Into<String>, samples: Vec<f32>) -> ExrChannel {
+        ExrChannel {
+            name: name.into(),
+            samples,
+        }
+    }
+}
+
+/// Averages radiance sums with their sample counts in the last element into
+/// red, green and blue channels, in `layer` if given.
+pub fn radiance_channels(sums: &[[f32; 4]], layer: Option<&str>) -> Vec<ExrChannel> {
+    ["R", "G", "B"]
+        .into_iter()
+        .enumerate()
+        .map(|(c, name)| {
+            let name = match layer {
+                Some(layer) => format!("{}.{}", layer, name),
+                None => name.to_string(),
+            };
+            let samples = sums.iter().map(|sum| sum[c] / sum[3].max(1.0)).collect();
+            ExrChannel::new(name, samples)
+        })
+        .collect()
+}
+
+/// Writes linear, unclamped channels to a losslessly compressed OpenEXR
+/// file.
+pub fn write_exr(
+    path: &str,
+    width: u32,
+    height: u32,
+    channels: Vec<ExrChannel>,
+    precision: ExrPrecision,
+) -> Result<()> {
+    let channels = channels
+        .into_iter()
+        .map(|channel| {
+            let samples = match precision {
+                ExrPrecision::Half => {
+                    FlatSamples::F16(channel.samples.into_iter().map(f16::from_f32).collect())
+                }
+                ExrPrecision::Float => FlatSamples::F32(channel.samples),
+            };
+            AnyChannel::new(channel.name.as_str(), samples)
+        })
+        .collect::<SmallVec<_>>();
+
+    let image = Image::from_encoded_channels(
+        (width as usize, height as usize),
+        Encoding::SMALL_FAST_LOSSLESS,
+        AnyChannels::sort(channels),
+    );
+    image.write().to_file(path)
+}
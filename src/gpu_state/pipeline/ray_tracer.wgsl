@@ -1,32 +1,113 @@
-@group(0) @binding(0) var colorBuffer: texture_storage_2d<rgba8unorm, write>;
+// Running sum of the samples of every pixel in rgb, and their number in w
+@group(0) @binding(0) var<storage, read_write> accumulation: array<vec4<f32>>;
+// Running sums of the auxiliary outputs of every pixel
+@group(0) @binding(1) var<storage, read_write> aovs: array<Aov>;
+// Sums of the luminance of the samples since the camera last moved in x, of
+// its square in y and their number in z
+@group(0) @binding(2) var<storage, read_write> moments: array<vec4<f32>>;
+@group(0) @binding(3) var<uniform> adaptive: AdaptiveSettings;
 
 @group(1) @binding(0) var<uniform> camera: Camera;
 @group(1) @binding(1) var<storage, read> objects: Geometries;
-@group(1) @binding(2) var<storage, read> sampleCount: u32;
+@group(1) @binding(2) var<uniform> sampleCount: u32;
+@group(1) @binding(3) var textures: texture_2d_array<f32>;
+@group(1) @binding(4) var textureSampler: sampler;
+@group(1) @binding(5) var<storage, read> textureDescriptors: Textures;
+@group(1) @binding(6) var<storage, read> materials: Materials;
+@group(1) @binding(7) var<uniform> sceneSettings: SceneSettings;
+@group(1) @binding(8) var densityGrid: texture_3d<f32>;
+@group(1) @binding(9) var measuredBrdfs: texture_3d<f32>;
+@group(1) @binding(10) var<uniform> pixelFilter: PixelFilter;
+@group(1) @binding(11) var<uniform> sheenAlbedo: SheenAlbedo;
 
 @group(2) @binding(0) var randomState: texture_storage_2d<r32uint, read_write>;
 
-@group(3) @binding(0) var prevBuffer: texture_storage_2d<rgba8unorm, read>;
+// The accumulation and the auxiliary outputs of the previous frame
+@group(3) @binding(0) var<storage, read> history: array<vec4<f32>>;
+@group(3) @binding(1) var<storage, read> aovHistory: array<Aov>;
+@group(3) @binding(2) var<uniform> reprojection: Reprojection;
 
 struct Material {
     color: vec3<f32>,
     kind: u32,
     fuzz_or_refractive_index: f32,
+    albedoTexture: i32,
+    roughnessTexture: i32,
+    emissionTexture: i32,
+    normalTexture: i32,
+    bumpTexture: i32,
+    bumpStrength: f32,
+    alpha: f32,
+    alphaTexture: i32,
+    clearcoat: f32,
+    clearcoatRoughness: f32,
+    filmThickness: f32,
+    filmRefractiveIndex: f32,
+    interiorMedium: i32,
+    priority: u32,
+    absorption: vec3<f32>,
+    anisotropy: f32,
+    meanFreePath: vec3<f32>,
+    roughnessTangent: f32,
+    roughnessBitangent: f32,
+    tangentRotation: f32,
+    sheenColor: vec3<f32>,
+    sheenRoughness: f32,
+    conductorEta: vec3<f32>,
+    measuredBrdf: u32,
+    conductorK: vec3<f32>,
+    mixTexture: i32,
+    mixMaterials: vec2<u32>,
+    mixFactorKind: u32,
+    mixFactor: f32,
+}
+
+struct Texture {
+    colorA: vec3<f32>,
+    kind: u32,
+    colorB: vec3<f32>,
+    scale: f32,
+    direction: vec2<f32>,
+    layerOrOctaves: u32,
+}
+
+struct Textures {
+    textures: array<Texture>,
+}
+
+struct Materials {
+    materials: array<Material>,
 }
 
 struct Geometry {
     center: vec3<f32>,
     radius: f32,
     u: vec3<f32>,
+    material: u32,
     v: vec3<f32>,
     kind: u32,
-    material: Material,
 }
 
 struct Geometries {
 	geometries: array<Geometry>,
 }
 
+struct SceneSettings {
+    // Index of the medium material filling the scene, or -1
+    globalMedium: i32,
+    volumeEnabled: u32,
+    volumeAnisotropy: f32,
+    volumeMajorant: f32,
+    volumeMin: vec3<f32>,
+    // Extinction at a density of 1
+    volumeExtinction: f32,
+    volumeMax: vec3<f32>,
+    volumeAlbedo: vec3<f32>,
+    volumeEmission: vec3<f32>,
+    // Index of the quad which is sampled as the light, or -1
+    light: i32,
+}
+
 struct Ray {
     direction: vec3<f32>,
     origin: vec3<f32>,
@@ -41,13 +122,108 @@ struct Camera {
 	up: vec3<f32>,
 }
 
+// Reconstruction filter tabulated for sampling
+struct PixelFilter {
+    // In pixels
+    radius: f32,
+    // Factor of every sample, above 1 only for filters with negative lobes
+    weightScale: f32,
+    // Cumulative distribution of the absolute filter over equally wide bins
+    // across the diameter in x, and the sign of the filter in the bin in y
+    bins: array<vec4<f32>, 64>,
+}
+
+// Directional albedo of a white sheen at 16 roughnesses times 16 cosines of
+// the view angle, both from 0 to 1, four to an element
+struct SheenAlbedo {
+    table: array<vec4<f32>, 64>,
+}
+
+struct AdaptiveSettings {
+    enabled: u32,
+    // Samples a pixel takes before its error is trusted
+    minSamples: u32,
+    // Relative standard error of the mean luminance below which a pixel
+    // is not traced anymore
+    threshold: f32,
+}
+
+struct Reprojection {
+    // The camera the history was rendered with
+    previousCamera: Camera,
+    // Whether the camera moved since the previous frame, so the history is
+    // reprojected instead of added to
+    reproject: u32,
+    // Most samples a reprojected pixel keeps
+    maxHistory: f32,
+}
+
 struct RenderState {
 	t: f32,
 	material: Material,
+    materialIndex: u32,
+    objectIndex: u32,
 	hit: bool,
 	position: vec3<f32>,
 	normal: vec3<f32>,
     frontFace: bool,
+    uv: vec2<f32>,
+    // Unlike normal these are not perturbed by normal or bump maps
+    geometricNormal: vec3<f32>,
+    dpdu: vec3<f32>,
+    dpdv: vec3<f32>,
+}
+
+// What a path sees at its first hit which is not a perfect reflection or
+// refraction. The indices are -1 when the path escapes without one, and are
+// those of the latest sample rather than sums.
+struct Aov {
+    albedo: vec3<f32>,
+    // Length of the path to the hit
+    depth: f32,
+    normal: vec3<f32>,
+    objectIndex: i32,
+    position: vec3<f32>,
+    materialIndex: i32,
+}
+
+// The outputs of the path being traced by rayColor
+var<private> firstHit: Aov;
+var<private> firstHitRecorded: bool;
+
+// A direction sampled from a material, and the throughput it carries, which
+// is the BSDF times the cosine over the pdf.
+struct BsdfSample {
+    direction: vec3<f32>,
+    weight: vec3<f32>,
+}
+
+struct LayerSample {
+    reflected: bool,
+    direction: vec3<f32>,
+    weight: vec3<f32>,
+}
+
+struct MediumInteraction {
+    scattered: bool,
+    ray: Ray,
+    weight: vec3<f32>,
+}
+
+// The dielectrics a path is inside of, so nested and touching dielectrics
+// refract with the refractive index of the right neighbour.
+struct InterfaceStack {
+    materials: array<u32, 4>,
+    size: u32,
+}
+
+struct VolumeCollision {
+    collided: bool,
+    // Set when tracking gave up inside the volume
+    absorbed: bool,
+    distance: f32,
+    position: vec3<f32>,
+    density: f32,
 }
 
 struct ScatteredRay {
@@ -66,8 +242,15 @@ fn random(screenPos: vec2<i32>) -> f32 {
 
 @compute @workgroup_size(1,1,1)
 fn main(@builtin(global_invocation_id) id: vec3<u32>) {
-    let screenSize: vec2<u32> = textureDimensions(colorBuffer);
+    let screenSize: vec2<u32> = textureDimensions(randomState);
     let screenPos: vec2<i32> = vec2<i32>(id.xy);
+    let pixel = id.y * screenSize.x + id.x;
+
+    // Every pixel is traced after the camera moved, since that restarts
+    // the moments.
+    if adaptive.enabled != 0u && sampleCount > 0u && converged(pixel) {
+        return;
+    }
 
     let horizontalCoefficient: f32 = (f32(screenPos.x) - f32(screenSize.x) / 2.0) / f32(screenSize.x);
     let verticalCoefficient: f32 = -(f32(screenPos.y) - f32(screenSize.y) / 2.0) / f32(screenSize.x);
@@ -82,14 +265,141 @@ fn main(@builtin(global_invocation_id) id: vec3<u32>) {
     
     var myRay1: Ray;
     myRay1.origin = camera.position + cameraShift;
-    let diff = 2.0/vec2<f32>(screenSize) * vec2<f32>(random(screenPos) - 0.5, random(screenPos) - 0.5);
+    // The offset from the pixel center is distributed like the filter, so
+    // the samples need no weights other than its sign.
+    let offsetX = sample_filter(random(screenPos));
+    let offsetY = sample_filter(random(screenPos));
+    let diff = vec2<f32>(offsetX.x, offsetY.x) / f32(screenSize.x);
     let toViewPlane = forwards + (horizontalCoefficient + diff.x)*right + (verticalCoefficient + diff.y)*up;
     myRay1.direction = camera.focusDist * toViewPlane - cameraShift;
-    pixelColor = rayColor(myRay1, screenPos);
+    let radiance = rayColor(myRay1, screenPos);
+    pixelColor = radiance * offsetX.y * offsetY.y * pixelFilter.weightScale;
     
-    let prevColor = textureLoad(prevBuffer, screenPos).xyz;
-    pixelColor = (f32(sampleCount) * prevColor + pixelColor)/f32(sampleCount + 1u);
-    textureStore(colorBuffer, screenPos, vec4<f32>(pixelColor, 1.0));
+    // The first sample after a reset overwrites the previous sum, unless
+    // the samples of the previous frame are reprojected.
+    var sum = vec4<f32>(0.0);
+    var aov: Aov;
+    if reprojection.reproject != 0u {
+        let previous = previous_pixel(myRay1.direction, screenSize);
+        if previous >= 0 {
+            let samples = history[previous].w;
+            let scale = min(samples, reprojection.maxHistory) / samples;
+            sum = history[previous] * scale;
+            aov = aovHistory[previous];
+            aov.albedo *= scale;
+            aov.depth *= scale;
+            aov.normal *= scale;
+            aov.position *= scale;
+        }
+    } else if sampleCount > 0u {
+        sum = accumulation[pixel];
+        aov = aovs[pixel];
+    }
+    accumulation[pixel] = sum + vec4<f32>(pixelColor, 1.0);
+
+    var moment = vec4<f32>(0.0);
+    if sampleCount > 0u {
+        moment = moments[pixel];
+    }
+    // The moments are of the radiance before the filter weight, whose sign
+    // and scale would inflate the variance of filters with negative lobes.
+    let luminance = dot(radiance, vec3<f32>(0.2126, 0.7152, 0.0722));
+    moments[pixel] = moment + vec4<f32>(luminance, luminance * luminance, 1.0, 0.0);
+
+    aov.albedo += firstHit.albedo;
+    aov.depth += firstHit.depth;
+    aov.normal += firstHit.normal;
+    aov.objectIndex = firstHit.objectIndex;
+    aov.position += firstHit.position;
+    aov.materialIndex = firstHit.materialIndex;
+    aovs[pixel] = aov;
+}
+
+// Offset in pixels from the center of the pixel along one axis, distributed
+// like the absolute filter, in x, and the sign of the filter there in y.
+fn sample_filter(u: f32) -> vec2<f32> {
+    // The first bin whose cumulative distribution reaches u
+    var low = 0u;
+    var high = 63u;
+    while low < high {
+        let middle = (low + high) / 2u;
+        if pixelFilter.bins[middle].x < u {
+            low = middle + 1u;
+        } else {
+            high = middle;
+        }
+    }
+
+    var start = 0.0;
+    if low > 0u {
+        start = pixelFilter.bins[low - 1u].x;
+    }
+    let end = pixelFilter.bins[low].x;
+    let t = clamp((u - start) / max(end - start, 1e-6), 0.0, 1.0);
+    let offset = (-1.0 + 2.0 * (f32(low) + t) / 64.0) * pixelFilter.radius;
+    return vec2<f32>(offset, pixelFilter.bins[low].y);
+}
+
+// Whether the relative standard error of the mean luminance of the pixel is
+// below the threshold of adaptive sampling.
+fn converged(pixel: u32) -> bool {
+    let moment = moments[pixel];
+    let samples = moment.z;
+    if samples < max(f32(adaptive.minSamples), 2.0) {
+        return false;
+    }
+
+    let mean = moment.x / samples;
+    let variance = max(moment.y / samples - mean * mean, 0.0) * samples / (samples - 1.0);
+    // Dark pixels are compared against a floor, or their error never falls.
+    let error = sqrt(variance / samples) / max(mean, 1e-3);
+    return error < adaptive.threshold;
+}
+
+// Index of the pixel of the previous frame which saw the first hit of the
+// path just traced, or -1 when that pixel saw another object, or the same
+// one at another depth or with another normal. Paths which escaped are
+// matched by their direction alone.
+fn previous_pixel(direction: vec3<f32>, screenSize: vec2<u32>) -> i32 {
+    let previousCamera = reprojection.previousCamera;
+    var toHit = firstHit.position - previousCamera.position;
+    if firstHit.objectIndex < 0 {
+        toHit = normalize(direction);
+    }
+
+    // Inverts the mapping from pixels to ray directions in main.
+    let forwards = dot(toHit, previousCamera.forwards);
+    if forwards <= 0.0 {
+        return -1;
+    }
+    let horizontalCoefficient = dot(toHit, previousCamera.right) / forwards;
+    let verticalCoefficient = dot(toHit, previousCamera.up) / forwards;
+    let width = f32(screenSize.x);
+    let x = i32(round(horizontalCoefficient * width + width / 2.0));
+    let y = i32(round(f32(screenSize.y) / 2.0 - verticalCoefficient * width));
+    if x < 0 || y < 0 || x >= i32(screenSize.x) || y >= i32(screenSize.y) {
+        return -1;
+    }
+
+    let index = y * i32(screenSize.x) + x;
+    let previous = aovHistory[index];
+    let samples = history[index].w;
+    if samples <= 0.0 || previous.objectIndex != firstHit.objectIndex {
+        return -1;
+    }
+    if firstHit.objectIndex < 0 {
+        return index;
+    }
+
+    let distance = length(toHit);
+    if abs(previous.depth / samples - distance) > 0.05 * distance + 0.01 {
+        return -1;
+    }
+    // Written so a pixel without a normal, which normalizes to NaN, fails.
+    if !(dot(normalize(previous.normal), firstHit.normal) > 0.9) {
+        return -1;
+    }
+    return index;
 }
 
 fn rayColor(ray: Ray, screenPos: vec2<i32>) -> vec3<f32> {
@@ -103,23 +413,175 @@ fn rayColor(ray: Ray, screenPos: vec2<i32>) -> vec3<f32> {
     temp_ray.origin = ray.origin;
     temp_ray.direction = ray.direction;
 
+    // Index of the medium material the ray is travelling through, if any
+    var medium = sceneSettings.globalMedium;
+    // Set once the path reaches a light or escapes the scene
+    var finished = false;
+    // Light emitted along the path by volumes, already weighted
+    var radiance = vec3<f32>(0.0);
+    var interfaces: InterfaceStack;
+    interfaces.size = 0u;
+    // Distance travelled between surfaces
+    var pathLength = 0.0;
+
+    firstHit.albedo = vec3<f32>(0.0);
+    firstHit.depth = 0.0;
+    firstHit.normal = vec3<f32>(0.0);
+    firstHit.objectIndex = -1;
+    firstHit.position = vec3<f32>(0.0);
+    firstHit.materialIndex = -1;
+    firstHitRecorded = false;
+
     let bounces: u32 = 10u;
-    for(var bounce: u32 = 0u; bounce < bounces; bounce++) {
+    // Scattering inside a medium is not a surface bounce. Dense media such
+    // as skin need many of them, so they have their own limit.
+    let mediumBounces: u32 = 256u;
+    // Crossing the boundary of a medium is neither, but the number of
+    // crossings still has to be limited.
+    let maxSteps: u32 = 512u;
+    var bounce: u32 = 0u;
+    var mediumBounce: u32 = 0u;
+    for(var step: u32 = 0u; step < maxSteps && bounce < bounces && mediumBounce < mediumBounces; step++) {
+
+        result = trace(temp_ray, screenPos);
+        if result.hit {
+            result = resolve_mix(result, temp_ray, screenPos);
+            result.material = apply_textures(result.material, result.uv, result.position);
+            result.normal = shading_normal(result, temp_ray.direction);
+        }
+
+        let unitDirection = normalize(temp_ray.direction);
+        var surfaceDistance = 1e30;
+        if result.hit {
+            surfaceDistance = result.t * length(temp_ray.direction);
+        }
+
+        var collision: VolumeCollision;
+        collision.collided = false;
+        collision.absorbed = false;
+        if sceneSettings.volumeEnabled != 0u {
+            collision = delta_track_volume(temp_ray.origin, unitDirection, surfaceDistance, screenPos);
+        }
+        if collision.absorbed {
+            break;
+        }
+
+        // Whichever medium the ray collides with first scatters it.
+        var mediumDistance = surfaceDistance;
+        if collision.collided {
+            mediumDistance = collision.distance;
+        }
+
+        if medium >= 0 {
+            let interaction = sample_medium(temp_ray.origin, unitDirection, mediumDistance, u32(medium), screenPos);
+            color = color * interaction.weight;
+            if interaction.scattered {
+                temp_ray = interaction.ray;
+                mediumBounce++;
+                continue;
+            }
+        }
 
-        result = trace(temp_ray);
+        if collision.collided {
+            // The absorbed part of the collision is where emission happens.
+            let albedo = sceneSettings.volumeAlbedo;
+            radiance += color * (1.0 - albedo) * collision.density * sceneSettings.volumeEmission;
+
+            let scattered = medium_scatter_direction(
+                collision.position,
+                unitDirection,
+                sceneSettings.volumeAnisotropy,
+                screenPos
+            );
+            let phase = henyey_greenstein(dot(unitDirection, scattered.direction), sceneSettings.volumeAnisotropy);
+            color = color * albedo * phase / scattered.pdf;
+            temp_ray.origin = collision.position;
+            temp_ray.direction = scattered.direction;
+            mediumBounce++;
+            continue;
+        }
 
         //Set up for next trace
         temp_ray.origin = result.position;
+        if result.hit {
+            pathLength += surfaceDistance;
+        }
+
+        // Medium boundaries only change which medium the ray is in.
+        if result.hit && result.material.kind == 4u {
+            if result.frontFace {
+                medium = i32(result.materialIndex);
+            } else {
+                medium = sceneSettings.globalMedium;
+            }
+            continue;
+        }
+
+        // Where dielectrics overlap only the one with the highest priority
+        // has a boundary, the others are passed through.
+        if result.hit && (result.material.kind == 2u || result.material.kind == 5u) {
+            let top = interface_top(&interfaces, -1);
+            let dominated = top >= 0 && top != i32(result.materialIndex)
+                && materials.materials[top].priority > result.material.priority;
+            if dominated {
+                if result.frontFace {
+                    interface_push(&interfaces, result.materialIndex);
+                } else {
+                    interface_remove(&interfaces, result.materialIndex);
+                }
+                continue;
+            }
+        }
+
+        bounce++;
+
+        if !firstHitRecorded && result.hit && !is_specular(result.material) {
+            firstHit.albedo = color * result.material.color;
+            firstHit.depth = pathLength;
+            firstHit.normal = result.normal;
+            firstHit.objectIndex = i32(result.objectIndex);
+            firstHit.position = result.position;
+            firstHit.materialIndex = i32(result.materialIndex);
+            firstHitRecorded = true;
+        }
+
+        // The clearcoat either reflects the ray or lets it through to the
+        // base material below it. A coat on a dielectric is the boundary
+        // itself, so its reflectance stands in for the Fresnel term of the
+        // base in proportion to the clearcoat. Other bases reflect back out
+        // through the coat.
+        var fresnelScale = 1.0;
+        var coated = false;
+        if result.hit && result.material.clearcoat > 0.0 && result.frontFace && result.material.kind != 3u {
+            let layer = sample_clearcoat(result, temp_ray.direction, screenPos);
+            color = color * layer.weight;
+            if layer.reflected {
+                temp_ray.direction = layer.direction;
+                if below_surface(result, temp_ray.direction) {
+                    break;
+                }
+                continue;
+            }
+            if result.material.kind == 2u {
+                fresnelScale = 1.0 - result.material.clearcoat;
+            } else if !is_metal(result.material) || result.material.filmThickness == 0.0 {
+                coated = true;
+            }
+        }
 
         var light_hit = false;
 
         var pdf = 1.0;
         var scatter = 1.0;
+        var attenuation = result.material.color;
         switch result.material.kind {
             case 0u, default {
                 temp_ray.direction = mixture_sample_ray_direction(result, screenPos);
                 pdf = mixture_sample_pdf(result, temp_ray.direction);
                 scatter = scatter_pdf(result, temp_ray);
+                if below_surface(result, temp_ray.direction) {
+                    pdf = 0.0;
+                }
             }
             case 1u: {
                 temp_ray.direction = metallic_ray_direction(
@@ -128,50 +590,569 @@ fn rayColor(ray: Ray, screenPos: vec2<i32>) -> vec3<f32> {
                     result.material.fuzz_or_refractive_index,
                     screenPos
                 );
+                if below_surface(result, temp_ray.direction) {
+                    pdf = 0.0;
+                }
             }
-            case 2u: {
-                var refractiveIndex = result.material.fuzz_or_refractive_index;
+            case 2u, 5u: {
+                // The refractive index ratio is relative to whichever
+                // dielectric is on the other side of the boundary.
+                let ownIndex = result.material.fuzz_or_refractive_index;
+                var refractiveIndex: f32;
                 if result.frontFace {
-                    refractiveIndex = 1.0/refractiveIndex;
+                    refractiveIndex = interface_refractive_index(&interfaces, -1) / ownIndex;
+                } else {
+                    refractiveIndex = ownIndex / interface_refractive_index(&interfaces, i32(result.materialIndex));
                 }
                 temp_ray.direction = dielectric_ray_direction(
                     temp_ray.direction,
                     result.normal,
                     refractiveIndex,
+                    fresnelScale,
                     screenPos,
                 );
+
+                // Refracted rays enter or leave the dielectric, and with it
+                // its interior medium. Subsurface materials are their own
+                // interior medium, whose albedo is applied by the random
+                // walk instead of the boundary.
+                if result.material.kind == 5u {
+                    attenuation = vec3<f32>(1.0);
+                }
+                if below_surface(result, temp_ray.direction) {
+                    if result.frontFace {
+                        interface_push(&interfaces, result.materialIndex);
+                    } else {
+                        interface_remove(&interfaces, result.materialIndex);
+                    }
+                    medium = interface_medium(&interfaces);
+                }
             }
             case 3u: {
                 light_hit = true;
             }
+            case 6u, 8u: {
+                let sampled = sample_anisotropic_ggx(result, temp_ray.direction, screenPos);
+                temp_ray.direction = sampled.direction;
+                attenuation = sampled.weight;
+                if below_surface(result, temp_ray.direction) {
+                    pdf = 0.0;
+                }
+            }
+            case 9u: {
+                let sampled = sample_measured(result, temp_ray.direction, screenPos);
+                temp_ray.direction = sampled.direction;
+                attenuation = sampled.weight;
+                if below_surface(result, temp_ray.direction) {
+                    pdf = 0.0;
+                }
+            }
+            case 7u: {
+                let sampled = sample_cloth(result, temp_ray.direction, screenPos);
+                temp_ray.direction = sampled.direction;
+                attenuation = sampled.weight;
+                if below_surface(result, temp_ray.direction) {
+                    pdf = 0.0;
+                }
+            }
         }
         
         //early exit
         if (!result.hit) {
             color = color * skyColor(temp_ray);
+            // Seen through glass or mirrors the sky is the albedo.
+            if !firstHitRecorded {
+                firstHit.albedo = color;
+                firstHitRecorded = true;
+            }
+            finished = true;
             break;
         }
 
         if pdf == 0.0 {
-            //result.hit = true;
             break;
         }
 
         //unpack color
-        color = color * (scatter * result.material.color / pdf);
+        color = color * (scatter * attenuation / pdf);
+        if coated {
+            color = color * coat_transmittance(result.material, dot(temp_ray.direction, result.normal));
+        }
 
         if light_hit {
-            result.hit = false;
+            finished = true;
             break;
         }
     }
 
     //Rays which reached terminal state and bounced indefinitely
-    if (result.hit) {
+    if !finished {
         color = vec3(0.0, 0.0, 0.0);
     }
 
-    return color;
+    return color + radiance;
+}
+
+// Material index of the highest priority dielectric on the stack, ignoring
+// the material at index excluded, or -1 if there is none.
+fn interface_top(stack: ptr<function, InterfaceStack>, excluded: i32) -> i32 {
+    var top = -1;
+    for (var i: u32 = 0u; i < (*stack).size; i++) {
+        let material = i32((*stack).materials[i]);
+        if material == excluded {
+            continue;
+        }
+        if top < 0 || materials.materials[material].priority >= materials.materials[top].priority {
+            top = material;
+        }
+    }
+    return top;
+}
+
+// Refractive index of the space the path is in, ignoring the material at
+// index excluded. Outside every dielectric it is air.
+fn interface_refractive_index(stack: ptr<function, InterfaceStack>, excluded: i32) -> f32 {
+    let top = interface_top(stack, excluded);
+    if top < 0 {
+        return 1.0;
+    }
+    return materials.materials[top].fuzz_or_refractive_index;
+}
+
+// Index of the medium the path is in given the dielectrics it is inside of.
+fn interface_medium(stack: ptr<function, InterfaceStack>) -> i32 {
+    let top = interface_top(stack, -1);
+    if top < 0 {
+        return sceneSettings.globalMedium;
+    }
+    if materials.materials[top].kind == 5u {
+        return top;
+    }
+    return materials.materials[top].interiorMedium;
+}
+
+// Paths inside more dielectrics than the stack holds forget the newest one.
+fn interface_push(stack: ptr<function, InterfaceStack>, material: u32) {
+    if (*stack).size < 4u {
+        (*stack).materials[(*stack).size] = material;
+        (*stack).size++;
+    }
+}
+
+fn interface_remove(stack: ptr<function, InterfaceStack>, material: u32) {
+    for (var i: u32 = 0u; i < (*stack).size; i++) {
+        if (*stack).materials[i] == material {
+            (*stack).size--;
+            (*stack).materials[i] = (*stack).materials[(*stack).size];
+            return;
+        }
+    }
+}
+
+// Samples how far the ray travels through a homogeneous medium before
+// scattering, up to maxDistance. The distance is sampled with the extinction
+// averaged over the color channels, and the weight corrects for the per
+// channel difference.
+fn sample_medium(
+    origin: vec3<f32>,
+    unitDirection: vec3<f32>,
+    maxDistance: f32,
+    mediumIndex: u32,
+    screenPos: vec2<i32>
+) -> MediumInteraction {
+    let medium = materials.materials[mediumIndex];
+    var scattering: vec3<f32>;
+    var extinction: vec3<f32>;
+    if medium.kind == 5u {
+        // Subsurface materials are described by the albedo in their color
+        // and the mean free path.
+        extinction = 1.0 / max(medium.meanFreePath, vec3<f32>(1e-6));
+        scattering = medium.color * extinction;
+    } else {
+        // Media store their scattering coefficient in color.
+        scattering = medium.color;
+        extinction = medium.absorption + scattering;
+    }
+    let meanExtinction = (extinction.r + extinction.g + extinction.b) / 3.0;
+
+    var interaction: MediumInteraction;
+    interaction.scattered = false;
+    interaction.weight = vec3<f32>(1.0);
+    if meanExtinction <= 0.0 {
+        return interaction;
+    }
+
+    let distance = -log(1.0 - random(screenPos)) / meanExtinction;
+    if distance < maxDistance {
+        let transmittance = exp(-extinction * distance);
+        let pdf = meanExtinction * exp(-meanExtinction * distance);
+
+        interaction.scattered = true;
+        interaction.ray.origin = origin + distance * unitDirection;
+        let scattered = medium_scatter_direction(
+            interaction.ray.origin,
+            unitDirection,
+            medium.anisotropy,
+            screenPos
+        );
+        interaction.ray.direction = scattered.direction;
+        let phase = henyey_greenstein(dot(unitDirection, scattered.direction), medium.anisotropy);
+        interaction.weight = scattering * transmittance / pdf * phase / scattered.pdf;
+    } else {
+        let transmittance = exp(-extinction * maxDistance);
+        let probability = exp(-meanExtinction * maxDistance);
+        interaction.weight = transmittance / probability;
+    }
+
+    return interaction;
+}
+
+// Finds the first real collision with the grid volume before maxDistance with
+// delta tracking: tentative collisions are sampled against the majorant and
+// accepted with the ratio of the local extinction to the majorant.
+fn delta_track_volume(
+    origin: vec3<f32>,
+    unitDirection: vec3<f32>,
+    maxDistance: f32,
+    screenPos: vec2<i32>
+) -> VolumeCollision {
+    var collision: VolumeCollision;
+    collision.collided = false;
+    collision.absorbed = false;
+
+    let majorant = sceneSettings.volumeMajorant;
+    if majorant <= 0.0 {
+        return collision;
+    }
+
+    // Slab test against the bounding box of the volume
+    let inverseDirection = 1.0 / unitDirection;
+    let t0 = (sceneSettings.volumeMin - origin) * inverseDirection;
+    let t1 = (sceneSettings.volumeMax - origin) * inverseDirection;
+    let tNear = min(t0, t1);
+    let tFar = max(t0, t1);
+    let tEnter = max(max(tNear.x, tNear.y), max(tNear.z, 0.0));
+    let tExit = min(min(tFar.x, tFar.y), min(tFar.z, maxDistance));
+    if tEnter >= tExit {
+        return collision;
+    }
+
+    var t = tEnter;
+    // Bounded so a dense volume cannot stall the shader. A path still inside
+    // after the last step is absorbed rather than let through, so the bound
+    // darkens the volume slightly instead of leaking light through it.
+    for (var i: u32 = 0u; i < 256u; i++) {
+        t -= log(1.0 - random(screenPos)) / majorant;
+        if t >= tExit {
+            return collision;
+        }
+
+        let position = origin + t * unitDirection;
+        let density = grid_density(position);
+        if random(screenPos) < density * sceneSettings.volumeExtinction / majorant {
+            collision.collided = true;
+            collision.distance = t;
+            collision.position = position;
+            collision.density = density;
+            return collision;
+        }
+    }
+
+    collision.absorbed = true;
+    return collision;
+}
+
+// Trilinearly interpolated density of the grid at a world space position.
+fn grid_density(position: vec3<f32>) -> f32 {
+    let size = vec3<i32>(textureDimensions(densityGrid));
+    let extent = sceneSettings.volumeMax - sceneSettings.volumeMin;
+    let cell = (position - sceneSettings.volumeMin) / extent * vec3<f32>(size) - 0.5;
+
+    let base = vec3<i32>(floor(cell));
+    let f = cell - floor(cell);
+
+    var density = 0.0;
+    for (var i: i32 = 0; i < 8; i++) {
+        let offset = vec3<i32>(i & 1, (i >> 1u) & 1, (i >> 2u) & 1);
+        let corner = clamp(base + offset, vec3<i32>(0), size - 1);
+        let weights = mix(1.0 - f, f, vec3<f32>(offset));
+        density += weights.x * weights.y * weights.z * textureLoad(densityGrid, corner, 0).r;
+    }
+
+    return density;
+}
+
+// Henyey-Greenstein phase function, where cosTheta is the cosine between the
+// direction of travel before and after scattering.
+fn henyey_greenstein(cosTheta: f32, g: f32) -> f32 {
+    let denominator = 1.0 + g * g - 2.0 * g * cosTheta;
+    return (1.0 - g * g) / (4.0 * radians(180.0) * denominator * sqrt(denominator));
+}
+
+fn sample_henyey_greenstein(direction: vec3<f32>, g: f32, screenPos: vec2<i32>) -> vec3<f32> {
+    let r1 = random(screenPos);
+    let r2 = random(screenPos);
+
+    var cosTheta: f32;
+    if abs(g) < 0.001 {
+        cosTheta = 1.0 - 2.0 * r1;
+    } else {
+        let term = (1.0 - g * g) / (1.0 - g + 2.0 * g * r1);
+        cosTheta = (1.0 + g * g - term * term) / (2.0 * g);
+    }
+    cosTheta = clamp(cosTheta, -1.0, 1.0);
+    let sinTheta = sqrt(1.0 - cosTheta * cosTheta);
+    let phi = 2.0 * radians(180.0) * r2;
+
+    let basis = orthonormal_basis(direction);
+    return normalize(sinTheta * cos(phi) * basis[0] + sinTheta * sin(phi) * basis[1] + cosTheta * direction);
+}
+
+// Samples either the light or the phase function, like surfaces do with
+// their mixture of light and cosine sampling. Without a light only the phase
+// function is sampled.
+fn medium_scatter_direction(position: vec3<f32>, direction: vec3<f32>, g: f32, screenPos: vec2<i32>) -> ScatteredRay {
+    var scattered: ScatteredRay;
+    if sceneSettings.light < 0 {
+        scattered.direction = sample_henyey_greenstein(direction, g, screenPos);
+        scattered.pdf = henyey_greenstein(dot(direction, scattered.direction), g);
+        return scattered;
+    }
+
+    if random(screenPos) < 0.5 {
+        scattered.direction = normalize(point_on_light(screenPos) - position);
+    } else {
+        scattered.direction = sample_henyey_greenstein(direction, g, screenPos);
+    }
+
+    scattered.pdf = 0.5 * light_pdf(position, scattered.direction)
+        + 0.5 * henyey_greenstein(dot(direction, scattered.direction), g);
+    return scattered;
+}
+
+// Two unit vectors perpendicular to the unit vector n and to each other.
+fn orthonormal_basis(n: vec3<f32>) -> mat2x3<f32> {
+    var helper = vec3<f32>(1.0, 0.0, 0.0);
+    if abs(n.x) > 0.9 {
+        helper = vec3<f32>(0.0, 1.0, 0.0);
+    }
+    let tangent = normalize(cross(n, helper));
+    return mat2x3<f32>(tangent, cross(n, tangent));
+}
+
+fn srgb_to_linear(color: vec3<f32>) -> vec3<f32> {
+    let low = color / 12.92;
+    let high = pow((color + 0.055) / 1.055, vec3<f32>(2.4));
+    return select(high, low, color <= vec3<f32>(0.04045));
+}
+
+// Evaluates the texture at the given index for a hit at uv and position.
+// Images used as colors are stored sRGB encoded, so isColor decodes them.
+fn evaluate_texture(index: i32, uv: vec2<f32>, position: vec3<f32>, isColor: bool) -> vec3<f32> {
+    let texture = textureDescriptors.textures[index];
+
+    switch texture.kind {
+        case 0u, default {
+            let color = textureSampleLevel(textures, textureSampler, uv, texture.layerOrOctaves, 0.0).rgb;
+            if isColor {
+                return srgb_to_linear(color);
+            }
+            return color;
+        }
+        case 1u {
+            let cell = vec2<i32>(floor(texture.scale * uv));
+            return select(texture.colorB, texture.colorA, (cell.x + cell.y) % 2 == 0);
+        }
+        case 2u {
+            let cell = vec3<i32>(floor(texture.scale * position));
+            return select(texture.colorB, texture.colorA, (cell.x + cell.y + cell.z) % 2 == 0);
+        }
+        case 3u {
+            let noise = 0.5 * (1.0 + fbm(texture.scale * position, texture.layerOrOctaves));
+            return mix(texture.colorA, texture.colorB, clamp(noise, 0.0, 1.0));
+        }
+        case 4u {
+            let p = texture.scale * position;
+            let vein = 0.5 * (1.0 + sin(p.z + 10.0 * turbulence(p, texture.layerOrOctaves)));
+            return mix(texture.colorA, texture.colorB, vein);
+        }
+        case 5u {
+            let t = clamp(dot(uv, texture.direction), 0.0, 1.0);
+            return mix(texture.colorA, texture.colorB, t);
+        }
+    }
+}
+
+// Replaces the textured parameters of the material with their value at the hit.
+fn apply_textures(material: Material, uv: vec2<f32>, position: vec3<f32>) -> Material {
+    var textured = material;
+
+    if material.kind == 3u {
+        if material.emissionTexture >= 0 {
+            textured.color *= evaluate_texture(material.emissionTexture, uv, position, true);
+        }
+        return textured;
+    }
+
+    if material.albedoTexture >= 0 {
+        textured.color *= evaluate_texture(material.albedoTexture, uv, position, true);
+    }
+
+    // Only metals use the parameter as a roughness, dielectrics store their
+    // refractive index in it.
+    if material.kind == 1u && material.roughnessTexture >= 0 {
+        textured.fuzz_or_refractive_index *= evaluate_texture(material.roughnessTexture, uv, position, false).r;
+    }
+
+    if (material.kind == 6u || material.kind == 8u) && material.roughnessTexture >= 0 {
+        let roughness = evaluate_texture(material.roughnessTexture, uv, position, false).r;
+        textured.roughnessTangent *= roughness;
+        textured.roughnessBitangent *= roughness;
+    }
+
+    return textured;
+}
+
+// Perturbs the normal of the hit with the bump and normal maps of its
+// material. The normal map is applied in the frame of the bumped surface.
+fn shading_normal(hitRecord: RenderState, rayDirection: vec3<f32>) -> vec3<f32> {
+    let material = hitRecord.material;
+    if material.normalTexture < 0 && material.bumpTexture < 0 {
+        return hitRecord.normal;
+    }
+
+    // The partial derivatives follow the orientation of the surface, not
+    // the side the ray hit it from.
+    var outwardNormal = hitRecord.geometricNormal;
+    if !hitRecord.frontFace {
+        outwardNormal = -outwardNormal;
+    }
+
+    var normal = outwardNormal;
+    if material.bumpTexture >= 0 {
+        let delta = 0.0005;
+        let uv = hitRecord.uv;
+        let position = hitRecord.position;
+        let height = evaluate_texture(material.bumpTexture, uv, position, false).r;
+        let heightU = evaluate_texture(
+            material.bumpTexture,
+            uv + vec2<f32>(delta, 0.0),
+            position + delta * hitRecord.dpdu,
+            false
+        ).r;
+        let heightV = evaluate_texture(
+            material.bumpTexture,
+            uv + vec2<f32>(0.0, delta),
+            position + delta * hitRecord.dpdv,
+            false
+        ).r;
+
+        let dpdu = hitRecord.dpdu + material.bumpStrength * (heightU - height) / delta * outwardNormal;
+        let dpdv = hitRecord.dpdv + material.bumpStrength * (heightV - height) / delta * outwardNormal;
+        normal = normalize(cross(dpdu, dpdv));
+        if dot(normal, outwardNormal) < 0.0 {
+            normal = -normal;
+        }
+    }
+
+    if material.normalTexture >= 0 {
+        // Tangent space normals follow the OpenGL convention where green
+        // points towards decreasing v.
+        let tangent = normalize(hitRecord.dpdu - dot(hitRecord.dpdu, normal) * normal);
+        var bitangent = cross(normal, tangent);
+        if dot(bitangent, hitRecord.dpdv) > 0.0 {
+            bitangent = -bitangent;
+        }
+        let m = 2.0 * evaluate_texture(material.normalTexture, hitRecord.uv, hitRecord.position, false) - 1.0;
+        normal = normalize(m.x * tangent + m.y * bitangent + m.z * normal);
+    }
+
+    if !hitRecord.frontFace {
+        normal = -normal;
+    }
+
+    return clamp_shading_normal(normal, hitRecord.geometricNormal, -normalize(rayDirection));
+}
+
+// A shading normal pointing below the geometric surface, or away from the
+// viewer, would let paths scatter into the surface. Such normals are bent
+// back towards the viewer just enough for it to see the shading side.
+fn clamp_shading_normal(normal: vec3<f32>, geometricNormal: vec3<f32>, toViewer: vec3<f32>) -> vec3<f32> {
+    var clamped = normal;
+    if dot(clamped, geometricNormal) <= 0.0 {
+        clamped = geometricNormal;
+    }
+
+    let minCosine = 0.01;
+    let cosine = dot(clamped, toViewer);
+    if cosine < minCosine {
+        clamped = normalize(clamped + (minCosine - cosine) * toViewer);
+    }
+
+    return clamped;
+}
+
+// Reflected directions are valid for the shading normal but can still leave
+// through the geometric surface, where they would light the wrong side.
+fn below_surface(hitRecord: RenderState, direction: vec3<f32>) -> bool {
+    return dot(direction, hitRecord.geometricNormal) <= 0.0;
+}
+
+fn hash(cell: vec3<i32>) -> u32 {
+    var h = u32(cell.x) * 73856093u ^ u32(cell.y) * 19349663u ^ u32(cell.z) * 83492791u;
+    h = (h ^ (h >> 16u)) * 0x45d9f3bu;
+    h = (h ^ (h >> 16u)) * 0x45d9f3bu;
+    return h ^ (h >> 16u);
+}
+
+// Pseudo random unit vector attached to a lattice point.
+fn lattice_gradient(cell: vec3<i32>) -> vec3<f32> {
+    let h = hash(cell);
+    let g = vec3<f32>(f32(h & 1023u), f32((h >> 10u) & 1023u), f32((h >> 20u) & 1023u)) / 511.5 - 1.0;
+    return normalize(g + vec3<f32>(0.0001, 0.0, 0.0));
+}
+
+// Gradient noise in the range [-1, 1].
+fn perlin(p: vec3<f32>) -> f32 {
+    let cell = vec3<i32>(floor(p));
+    let f = fract(p);
+    let fade = f * f * f * (f * (f * 6.0 - 15.0) + 10.0);
+
+    var corners: array<f32, 8>;
+    for (var i: i32 = 0; i < 8; i++) {
+        let offset = vec3<i32>(i & 1, (i >> 1u) & 1, (i >> 2u) & 1);
+        corners[i] = dot(lattice_gradient(cell + offset), f - vec3<f32>(offset));
+    }
+
+    let x00 = mix(corners[0], corners[1], fade.x);
+    let x10 = mix(corners[2], corners[3], fade.x);
+    let x01 = mix(corners[4], corners[5], fade.x);
+    let x11 = mix(corners[6], corners[7], fade.x);
+    return mix(mix(x00, x10, fade.y), mix(x01, x11, fade.y), fade.z);
+}
+
+fn fbm(p: vec3<f32>, octaves: u32) -> f32 {
+    var sum = 0.0;
+    var amplitude = 0.5;
+    var frequency = 1.0;
+    for (var i: u32 = 0u; i < max(octaves, 1u); i++) {
+        sum += amplitude * perlin(frequency * p);
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+    return sum;
+}
+
+fn turbulence(p: vec3<f32>, octaves: u32) -> f32 {
+    var sum = 0.0;
+    var amplitude = 1.0;
+    var frequency = 1.0;
+    for (var i: u32 = 0u; i < max(octaves, 1u); i++) {
+        sum += amplitude * abs(perlin(frequency * p));
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+    return sum;
 }
 
 fn scatter_pdf(hitRecord: RenderState, scattered: Ray) -> f32 {
@@ -198,28 +1179,43 @@ fn cosine_sample_pdf(hitRecord: RenderState, direction: vec3<f32>) -> f32 {
 }
 
 fn light_sample_ray_direction(hitRecord: RenderState, screenPos: vec2<i32>) -> vec3<f32> {
-    let quad = objects.geometries[0];
-    let on_light = quad.center + quad.u * random(screenPos) + quad.v * random(screenPos);
-    let to_light = on_light - hitRecord.position;
+    let to_light = point_on_light(screenPos) - hitRecord.position;
     return normalize(to_light);
 }
 
+// Uniformly distributed point on the light, which the scene has to have.
+fn point_on_light(screenPos: vec2<i32>) -> vec3<f32> {
+    let quad = objects.geometries[sceneSettings.light];
+    return quad.center + quad.u * random(screenPos) + quad.v * random(screenPos);
+}
+
 fn light_sample_pdf(hitRecord: RenderState, direction: vec3<f32>) -> f32 {
     if (dot(direction, hitRecord.normal) < 0.0) {
         return 0.0;
     }
 
+    return light_pdf(hitRecord.position, direction);
+}
+
+// Solid angle density of sampling the direction from position by picking a
+// uniform point on the light.
+fn light_pdf(position: vec3<f32>, direction: vec3<f32>) -> f32 {
+    if sceneSettings.light < 0 {
+        return 0.0;
+    }
+
     var ray: Ray;
-    ray.origin = hitRecord.position;
+    ray.origin = position;
     ray.direction = direction;
-    let hitResult = hit_quad(ray, objects.geometries[0], 0.001, 9999.0);
+    let quad = objects.geometries[sceneSettings.light];
+    let hitResult = hit_quad(ray, quad, 0.001, 9999.0);
 
     if !hitResult.hit {
         return 0.0;
     }
 
     let distance_squared = hitResult.t * hitResult.t;
-    let light_area = 1.0;
+    let light_area = length(cross(quad.u, quad.v));
     let light_cosine = abs(dot(hitResult.normal, direction));
     //if (light_cosine < 0.001) {
     //    return sr;
@@ -228,14 +1224,18 @@ fn light_sample_pdf(hitRecord: RenderState, direction: vec3<f32>) -> f32 {
     return distance_squared / (light_cosine * light_area);
 }
 
+// Without a light only the cosine is sampled.
 fn mixture_sample_ray_direction(hitRecord: RenderState, screenPos: vec2<i32>) -> vec3<f32> {
-    if (random(screenPos) < 0.5) {
+    if sceneSettings.light >= 0 && random(screenPos) < 0.5 {
         return light_sample_ray_direction(hitRecord, screenPos);
     }
     return cosine_sample_ray_direction(hitRecord, screenPos);
 }
 
 fn mixture_sample_pdf(hitRecord: RenderState, direction: vec3<f32>) -> f32 {
+    if sceneSettings.light < 0 {
+        return cosine_sample_pdf(hitRecord, direction);
+    }
     return 0.5 * light_sample_pdf(hitRecord, direction) + 0.5 * cosine_sample_pdf(hitRecord, direction);
 }
 
@@ -251,10 +1251,13 @@ fn metallic_ray_direction(rayDirection: vec3<f32>, normal: vec3<f32>, fuzz: f32,
     return vec3<f32>(0.0, 0.0, 0.0);
 }
 
+// The Fresnel reflectance is scaled by fresnelScale, which lets a coat that
+// already accounted for the reflection at this boundary take its place.
 fn dielectric_ray_direction(
     rayDirection: vec3<f32>, 
     normal: vec3<f32>, 
     refractiveIndex: f32,
+    fresnelScale: f32,
     screenPos: vec2<i32>
 ) -> vec3<f32> {
     let unitDirection = normalize(rayDirection);
@@ -262,13 +1265,429 @@ fn dielectric_ray_direction(
     let sinTheta = sqrt(1.0 - cosTheta*cosTheta);
 
     let cannot_refract = refractiveIndex * sinTheta > 1.0;
-    if (cannot_refract || reflectance(cosTheta, refractiveIndex) > random(screenPos)) {
+    if (cannot_refract || fresnelScale * reflectance(cosTheta, refractiveIndex) > random(screenPos)) {
         return reflect(unitDirection, normal);
     }
 
     return refract(unitDirection, normal, refractiveIndex);
 }
 
+// Picks between the clearcoat and the base material in proportion to the
+// reflectance of the coat, so the base receives what the coat lets through.
+// What the base reflects back out is attenuated again by coat_transmittance.
+// A film on a metal is not a separate layer, since the stack of film and
+// metal reflects what the metal alone would, so in proportion to the
+// clearcoat it stands in for the Fresnel term of the metal.
+fn sample_clearcoat(hitRecord: RenderState, rayDirection: vec3<f32>, screenPos: vec2<i32>) -> LayerSample {
+    let material = hitRecord.material;
+    let unitDirection = normalize(rayDirection);
+    let cosTheta = clamp(dot(-unitDirection, hitRecord.normal), 0.0, 1.0);
+    let coatReflectance = coat_reflectance(material, cosTheta);
+
+    let filmOnMetal = is_metal(material) && material.filmThickness > 0.0;
+    var layer: LayerSample;
+    var probability = clamp((coatReflectance.r + coatReflectance.g + coatReflectance.b) / 3.0, 0.001, 0.999);
+    if filmOnMetal {
+        probability = material.clearcoat;
+    }
+    if random(screenPos) < probability {
+        layer.reflected = true;
+        layer.direction = metallic_ray_direction(
+            unitDirection,
+            hitRecord.normal,
+            material.clearcoatRoughness,
+            screenPos
+        );
+        layer.weight = coatReflectance / probability;
+    } else {
+        layer.reflected = false;
+        layer.weight = (1.0 - coatReflectance) / (1.0 - probability);
+        if filmOnMetal {
+            layer.weight = vec3<f32>(1.0);
+        }
+    }
+
+    return layer;
+}
+
+// Fraction of the light leaving the base at the given cosine which gets
+// through the coat.
+fn coat_transmittance(material: Material, cosTheta: f32) -> vec3<f32> {
+    return 1.0 - coat_reflectance(material, clamp(cosTheta, 0.0, 1.0));
+}
+
+// Reflectance of the clearcoat, scaled by its strength. Dielectrics and
+// metals are coated by the film directly, other materials through a lacquer
+// with a refractive index of 1.5, which is also the coat without a film.
+fn coat_reflectance(material: Material, cosTheta: f32) -> vec3<f32> {
+    var reflectance = vec3<f32>(reflectance(cosTheta, 1.0 / 1.5));
+    if material.filmThickness > 0.0 {
+        var baseEta = vec3<f32>(1.5);
+        var baseK = vec3<f32>(0.0);
+        if material.kind == 2u {
+            baseEta = vec3<f32>(material.fuzz_or_refractive_index);
+        } else if material.kind == 8u {
+            baseEta = material.conductorEta;
+            baseK = material.conductorK;
+        } else if is_metal(material) {
+            let index = metal_refractive_index(material.color);
+            baseEta = index[0];
+            baseK = index[1];
+        }
+        reflectance = thin_film_reflectance(
+            cosTheta,
+            material.filmThickness,
+            material.filmRefractiveIndex,
+            baseEta,
+            baseK
+        );
+    }
+    return reflectance * material.clearcoat;
+}
+
+fn is_metal(material: Material) -> bool {
+    return material.kind == 1u || material.kind == 6u || material.kind == 8u;
+}
+
+// Complex refractive index eta + ik of a metal reflecting color at normal
+// incidence, with the same color as its edge tint, after Gulbrandsen's
+// "Artist Friendly Metallic Fresnel".
+fn metal_refractive_index(color: vec3<f32>) -> mat2x3<f32> {
+    let r = clamp(color, vec3<f32>(0.0), vec3<f32>(0.99));
+    let g = clamp(color, vec3<f32>(0.0), vec3<f32>(1.0));
+    let sqrtR = sqrt(r);
+    let eta = g * (1.0 - r) / (1.0 + r) + (1.0 - g) * (1.0 + sqrtR) / (1.0 - sqrtR);
+    let k2 = ((eta + 1.0) * (eta + 1.0) * r - (eta - 1.0) * (eta - 1.0)) / (1.0 - r);
+    return mat2x3<f32>(eta, sqrt(max(k2, vec3<f32>(0.0))));
+}
+
+// Reflectance of a film of the given thickness in nanometers, lying between
+// air and a base of complex refractive index baseEta + i baseK, which is real
+// for dielectrics. The two reflected waves interfere differently at each
+// wavelength, which gives the iridescence.
+fn thin_film_reflectance(
+    cosTheta: f32,
+    thickness: f32,
+    filmIndex: f32,
+    baseEta: vec3<f32>,
+    baseK: vec3<f32>
+) -> vec3<f32> {
+    let sin2Theta = 1.0 - cosTheta * cosTheta;
+    let sin2Film = sin2Theta / (filmIndex * filmIndex);
+    if sin2Film >= 1.0 {
+        return vec3<f32>(1.0);
+    }
+    let cosFilm = sqrt(1.0 - sin2Film);
+
+    // Amplitude reflection coefficients of the air to film interface per
+    // polarization
+    let r12s = (cosTheta - filmIndex * cosFilm) / (cosTheta + filmIndex * cosFilm);
+    let r12p = (filmIndex * cosTheta - cosFilm) / (filmIndex * cosTheta + cosFilm);
+
+    // Representative red, green and blue wavelengths in nanometers
+    let wavelengths = vec3<f32>(650.0, 550.0, 450.0);
+    let phase = 4.0 * radians(180.0) * filmIndex * thickness * cosFilm / wavelengths;
+
+    var result: vec3<f32>;
+    for (var i = 0; i < 3; i++) {
+        // The film to base coefficients are complex, whose phase shifts
+        // the interference.
+        let base = vec2<f32>(baseEta[i], baseK[i]);
+        let cosBase = complex_sqrt(vec2<f32>(1.0, 0.0) - complex_div(vec2<f32>(sin2Theta, 0.0), complex_mul(base, base)));
+        let film = vec2<f32>(filmIndex * cosFilm, 0.0);
+        let baseCos = complex_mul(base, cosBase);
+        let r23s = complex_div(film - baseCos, film + baseCos);
+        let crossFilm = base * cosFilm;
+        let crossBase = filmIndex * cosBase;
+        let r23p = complex_div(crossFilm - crossBase, crossFilm + crossBase);
+
+        result[i] = 0.5 * (airy_reflectance(r12s, r23s, phase[i]) + airy_reflectance(r12p, r23p, phase[i]));
+    }
+    return result;
+}
+
+fn airy_reflectance(r12: f32, r23: vec2<f32>, phase: f32) -> f32 {
+    let r23Length = length(r23);
+    let interference = 2.0 * r12 * r23Length * cos(phase + atan2(r23.y, r23.x));
+    let r12Squared = r12 * r12;
+    let r23Squared = r23Length * r23Length;
+    return (r12Squared + r23Squared + interference) / (1.0 + r12Squared * r23Squared + interference);
+}
+
+fn complex_mul(a: vec2<f32>, b: vec2<f32>) -> vec2<f32> {
+    return vec2<f32>(a.x * b.x - a.y * b.y, a.x * b.y + a.y * b.x);
+}
+
+fn complex_div(a: vec2<f32>, b: vec2<f32>) -> vec2<f32> {
+    return complex_mul(a, vec2<f32>(b.x, -b.y)) / dot(b, b);
+}
+
+// Principal square root, whose real part is not negative.
+fn complex_sqrt(z: vec2<f32>) -> vec2<f32> {
+    let r = length(z);
+    let re = sqrt(max(0.5 * (r + z.x), 0.0));
+    var im = sqrt(max(0.5 * (r - z.x), 0.0));
+    if z.y < 0.0 {
+        im = -im;
+    }
+    return vec2<f32>(re, im);
+}
+
+// Tangent, bitangent and normal of the shading frame. The tangent follows u,
+// which is the first arm of a quad, turned around the normal by rotation.
+fn shading_frame(hitRecord: RenderState, rotation: f32) -> mat3x3<f32> {
+    let normal = hitRecord.normal;
+    var tangent = hitRecord.dpdu - dot(hitRecord.dpdu, normal) * normal;
+    if dot(tangent, tangent) < 1e-12 {
+        tangent = orthonormal_basis(normal)[0];
+    }
+    tangent = normalize(tangent);
+    tangent = cos(rotation) * tangent + sin(rotation) * cross(normal, tangent);
+    return mat3x3<f32>(tangent, cross(normal, tangent), normal);
+}
+
+// Smith masking term of the anisotropic GGX distribution for a direction in
+// the shading frame.
+fn ggx_lambda(w: vec3<f32>, alpha: vec2<f32>) -> f32 {
+    let a2 = (alpha.x * alpha.x * w.x * w.x + alpha.y * alpha.y * w.y * w.y) / (w.z * w.z);
+    return 0.5 * (-1.0 + sqrt(1.0 + a2));
+}
+
+// Samples a microfacet normal from the distribution of normals visible from
+// the view direction, following Heitz 2018.
+fn sample_ggx_visible_normal(view: vec3<f32>, alpha: vec2<f32>, screenPos: vec2<i32>) -> vec3<f32> {
+    let stretched = normalize(vec3<f32>(alpha.x * view.x, alpha.y * view.y, view.z));
+
+    let lengthSquared = stretched.x * stretched.x + stretched.y * stretched.y;
+    var t1 = vec3<f32>(1.0, 0.0, 0.0);
+    if lengthSquared > 0.0 {
+        t1 = vec3<f32>(-stretched.y, stretched.x, 0.0) / sqrt(lengthSquared);
+    }
+    let t2 = cross(stretched, t1);
+
+    let r = sqrt(random(screenPos));
+    let phi = 2.0 * radians(180.0) * random(screenPos);
+    let p1 = r * cos(phi);
+    let s = 0.5 * (1.0 + stretched.z);
+    let p2 = (1.0 - s) * sqrt(1.0 - p1 * p1) + s * r * sin(phi);
+
+    let normal = p1 * t1 + p2 * t2 + sqrt(max(0.0, 1.0 - p1 * p1 - p2 * p2)) * stretched;
+    return normalize(vec3<f32>(alpha.x * normal.x, alpha.y * normal.y, max(0.0, normal.z)));
+}
+
+fn schlick_fresnel(f0: vec3<f32>, cosine: f32) -> vec3<f32> {
+    return f0 + (1.0 - f0) * pow(1.0 - clamp(cosine, 0.0, 1.0), 5.0);
+}
+
+// Exact Fresnel reflectance of a conductor with the complex refractive index
+// eta + ik, for unpolarized light.
+fn conductor_fresnel(eta: vec3<f32>, k: vec3<f32>, cosine: f32) -> vec3<f32> {
+    let cos2 = clamp(cosine * cosine, 0.0, 1.0);
+    let sin2 = 1.0 - cos2;
+    let eta2 = eta * eta;
+    let k2 = k * k;
+
+    let t0 = eta2 - k2 - sin2;
+    let a2PlusB2 = sqrt(t0 * t0 + 4.0 * eta2 * k2);
+    let t1 = a2PlusB2 + cos2;
+    let a = sqrt(max(vec3<f32>(0.0), 0.5 * (a2PlusB2 + t0)));
+    let t2 = 2.0 * sqrt(cos2) * a;
+    let perpendicular = (t1 - t2) / (t1 + t2);
+
+    let t3 = cos2 * a2PlusB2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let parallel = perpendicular * (t3 - t4) / (t3 + t4);
+
+    return 0.5 * (perpendicular + parallel);
+}
+
+// Brushed metal: a GGX conductor with separate roughness along the tangent
+// and the bitangent, tinted by the Schlick Fresnel of its color. Conductors
+// with a complex refractive index use the exact Fresnel, tinted by the color.
+fn sample_anisotropic_ggx(hitRecord: RenderState, rayDirection: vec3<f32>, screenPos: vec2<i32>) -> BsdfSample {
+    let material = hitRecord.material;
+    let frame = shading_frame(hitRecord, material.tangentRotation);
+    let alpha = max(vec2<f32>(material.roughnessTangent, material.roughnessBitangent), vec2<f32>(0.001));
+
+    let view = -normalize(rayDirection) * frame;
+    var sampled: BsdfSample;
+    if view.z <= 0.0 {
+        sampled.direction = hitRecord.normal;
+        sampled.weight = vec3<f32>(0.0);
+        return sampled;
+    }
+
+    let microfacet = sample_ggx_visible_normal(view, alpha, screenPos);
+    let light = reflect(-view, microfacet);
+    sampled.direction = frame * light;
+    if light.z <= 0.0 {
+        sampled.weight = vec3<f32>(0.0);
+        return sampled;
+    }
+
+    // With visible normal sampling everything but the Fresnel and the
+    // masking of the light direction cancels out.
+    let lambdaView = ggx_lambda(view, alpha);
+    let shadowing = (1.0 + lambdaView) / (1.0 + lambdaView + ggx_lambda(light, alpha));
+    let cosine = dot(view, microfacet);
+    var fresnel = schlick_fresnel(material.color, cosine);
+    if material.kind == 8u {
+        fresnel = material.color * conductor_fresnel(material.conductorEta, material.conductorK, cosine);
+    }
+    sampled.weight = fresnel * shadowing;
+    return sampled;
+}
+
+// Charlie sheen distribution of Estevez and Kulla, where most microfibers
+// lie close to the surface.
+fn charlie_distribution(cosTheta: f32, alpha: f32) -> f32 {
+    let sinTheta = sqrt(max(0.0, 1.0 - cosTheta * cosTheta));
+    return (2.0 + 1.0 / alpha) * pow(sinTheta, 1.0 / alpha) / (2.0 * radians(180.0));
+}
+
+// Visibility term of Neubelt and Pettineo for the Charlie distribution.
+fn cloth_visibility(cosLight: f32, cosView: f32) -> f32 {
+    return 1.0 / (4.0 * (cosLight + cosView - cosLight * cosView));
+}
+
+// Density of sampling the light direction through a Charlie distributed
+// half vector.
+fn sheen_pdf(normal: vec3<f32>, view: vec3<f32>, light: vec3<f32>, alpha: f32) -> f32 {
+    let half = normalize(view + light);
+    let cosHalf = dot(normal, half);
+    if cosHalf <= 0.0 {
+        return 0.0;
+    }
+    return charlie_distribution(cosHalf, alpha) * cosHalf / (4.0 * abs(dot(view, half)));
+}
+
+// Fabric: a Lambertian base under a sheen lobe which brightens grazing
+// angles. The base only gets what the sheen does not reflect, and the sheen
+// is scaled down where it alone would reflect more than 1. Directions come
+// from the light, the cosine or the sheen in equal proportions, or from the
+// latter two without a light.
+fn sample_cloth(hitRecord: RenderState, rayDirection: vec3<f32>, screenPos: vec2<i32>) -> BsdfSample {
+    let material = hitRecord.material;
+    let normal = hitRecord.normal;
+    let view = -normalize(rayDirection);
+    let alpha = max(material.sheenRoughness, 0.01);
+
+    var lightProbability = 0.0;
+    if sceneSettings.light >= 0 {
+        lightProbability = 1.0 / 3.0;
+    }
+    let otherProbability = 0.5 * (1.0 - lightProbability);
+
+    var sampled: BsdfSample;
+    let choice = random(screenPos);
+    if choice < lightProbability {
+        sampled.direction = light_sample_ray_direction(hitRecord, screenPos);
+    } else if choice < lightProbability + otherProbability {
+        sampled.direction = cosine_sample_ray_direction(hitRecord, screenPos);
+    } else {
+        let sinHalf = pow(random(screenPos), alpha / (2.0 * alpha + 1.0));
+        let cosHalf = sqrt(max(0.0, 1.0 - sinHalf * sinHalf));
+        let phi = 2.0 * radians(180.0) * random(screenPos);
+        let basis = orthonormal_basis(normal);
+        let half = sinHalf * cos(phi) * basis[0] + sinHalf * sin(phi) * basis[1] + cosHalf * normal;
+        sampled.direction = reflect(-view, half);
+    }
+
+    let cosLight = dot(normal, sampled.direction);
+    let cosView = dot(normal, view);
+    let pdf = lightProbability * light_sample_pdf(hitRecord, sampled.direction)
+        + otherProbability * (cosine_sample_pdf(hitRecord, sampled.direction)
+        + sheen_pdf(normal, view, sampled.direction, alpha));
+    if cosLight <= 0.0 || cosView <= 0.0 || pdf <= 0.0 {
+        sampled.weight = vec3<f32>(0.0);
+        return sampled;
+    }
+
+    let whiteSheenAlbedo = sheen_albedo(cosView, material.sheenRoughness);
+    let half = normalize(view + sampled.direction);
+    let sheen = material.sheenColor * charlie_distribution(dot(normal, half), alpha)
+        * cloth_visibility(cosLight, cosView) / max(whiteSheenAlbedo, 1.0);
+    let sheenReflectance = max(max(material.sheenColor.r, material.sheenColor.g), material.sheenColor.b)
+        * min(whiteSheenAlbedo, 1.0);
+    let diffuse = material.color / radians(180.0) * (1.0 - sheenReflectance);
+    sampled.weight = (diffuse + sheen) * cosLight / pdf;
+    return sampled;
+}
+
+// Bilinearly interpolates the tabulated albedo of a white sheen.
+fn sheen_albedo(cosView: f32, roughness: f32) -> f32 {
+    let x = clamp(cosView, 0.0, 1.0) * 15.0;
+    let y = clamp(roughness, 0.0, 1.0) * 15.0;
+    let x0 = min(floor(x), 14.0);
+    let y0 = min(floor(y), 14.0);
+    let fx = x - x0;
+    let fy = y - y0;
+    let low = mix(sheen_albedo_at(u32(y0), u32(x0)), sheen_albedo_at(u32(y0), u32(x0) + 1u), fx);
+    let high = mix(sheen_albedo_at(u32(y0) + 1u, u32(x0)), sheen_albedo_at(u32(y0) + 1u, u32(x0) + 1u), fx);
+    return mix(low, high, fy);
+}
+
+fn sheen_albedo_at(roughness: u32, cosine: u32) -> f32 {
+    let index = roughness * 16u + cosine;
+    return sheenAlbedo.table[index / 4u][index % 4u];
+}
+
+fn rotate_vector(vector: vec3<f32>, axis: vec3<f32>, angle: f32) -> vec3<f32> {
+    let cosine = cos(angle);
+    return vector * cosine + axis * dot(axis, vector) * (1.0 - cosine) + cross(axis, vector) * sin(angle);
+}
+
+// Looks up a MERL table with the light and view directions in a frame whose
+// z axis is the normal, through the half and difference angles of
+// Rusinkiewicz. The half elevation is sampled more densely near the normal.
+fn measured_brdf(table: u32, light: vec3<f32>, view: vec3<f32>) -> vec3<f32> {
+    let halfAngle = radians(90.0);
+    let half = normalize(light + view);
+    let thetaHalf = acos(clamp(half.z, -1.0, 1.0));
+    let phiHalf = atan2(half.y, half.x);
+
+    let difference = rotate_vector(
+        rotate_vector(light, vec3<f32>(0.0, 0.0, 1.0), -phiHalf),
+        vec3<f32>(0.0, 1.0, 0.0),
+        -thetaHalf
+    );
+    let thetaDiff = acos(clamp(difference.z, -1.0, 1.0));
+    var phiDiff = atan2(difference.y, difference.x);
+    if phiDiff < 0.0 {
+        phiDiff += radians(180.0);
+    }
+
+    let thetaHalfIndex = clamp(i32(sqrt(thetaHalf / halfAngle) * 90.0), 0, 89);
+    let thetaDiffIndex = clamp(i32(thetaDiff / halfAngle * 90.0), 0, 89);
+    let phiDiffIndex = clamp(i32(phiDiff / radians(180.0) * 180.0), 0, 179);
+
+    let texel = vec3<i32>(phiDiffIndex, thetaDiffIndex, thetaHalfIndex + 90 * i32(table));
+    return textureLoad(measuredBrdfs, texel, 0).rgb;
+}
+
+// A measured material, sampled like a Lambertian one from the light and the
+// cosine in equal proportions.
+fn sample_measured(hitRecord: RenderState, rayDirection: vec3<f32>, screenPos: vec2<i32>) -> BsdfSample {
+    let material = hitRecord.material;
+    let normal = hitRecord.normal;
+    let view = -normalize(rayDirection);
+
+    var sampled: BsdfSample;
+    sampled.direction = mixture_sample_ray_direction(hitRecord, screenPos);
+    let pdf = mixture_sample_pdf(hitRecord, sampled.direction);
+    let cosLight = dot(normal, sampled.direction);
+    if cosLight <= 0.0 || dot(normal, view) <= 0.0 || pdf <= 0.0 {
+        sampled.weight = vec3<f32>(0.0);
+        return sampled;
+    }
+
+    let basis = orthonormal_basis(normal);
+    let toLocal = transpose(mat3x3<f32>(basis[0], basis[1], normal));
+    let brdf = measured_brdf(material.measuredBrdf, toLocal * sampled.direction, toLocal * view);
+    sampled.weight = material.color * brdf * cosLight / pdf;
+    return sampled;
+}
+
 fn reflectance(cosine: f32, refIdx: f32) -> f32 {
     // Use Schlick's approximation for reflectance.
     var r0 = (1.0 - refIdx) / (1.0 + refIdx);
@@ -286,23 +1705,24 @@ fn random_unit_vector(screenPos: vec2<i32>) -> vec3<f32> {
     return vec3<f32>(x,y,z);
 }
 
-fn trace(ray: Ray) -> RenderState {
+fn trace(ray: Ray, screenPos: vec2<i32>) -> RenderState {
     var renderState: RenderState;
     var nearestHit: f32 = 9999.0;
     
 	for (var i: u32 = 0u; i < arrayLength(&objects.geometries); i++) {
-        var newRenderState: RenderState;
-
-        switch objects.geometries[i].kind {
-            case 0u, default {
-                newRenderState = hit_sphere(ray, objects.geometries[i], 0.001, nearestHit);        
-            }
-            case 1u {
-                newRenderState = hit_quad(ray, objects.geometries[i], 0.001, nearestHit);
+        var newRenderState = hit_geometry(ray, objects.geometries[i], 0.001, nearestHit);
+
+        // Rays pass through masked out hits without scattering. A sphere
+        // can still be hit on its far side.
+        if newRenderState.hit && !is_opaque(newRenderState, screenPos) {
+            newRenderState = hit_geometry(ray, objects.geometries[i], newRenderState.t + 0.001, nearestHit);
+            if newRenderState.hit && !is_opaque(newRenderState, screenPos) {
+                newRenderState.hit = false;
             }
-        } 
+        }
         
         if (newRenderState.hit) {
+            newRenderState.objectIndex = i;
             nearestHit = newRenderState.t;
             renderState = newRenderState;
         }
@@ -311,6 +1731,90 @@ fn trace(ray: Ray) -> RenderState {
     return renderState;
 }
 
+fn hit_geometry(ray: Ray, geometry: Geometry, tMin: f32, tMax: f32) -> RenderState {
+    switch geometry.kind {
+        case 0u, default {
+            return hit_sphere(ray, geometry, tMin, tMax);
+        }
+        case 1u {
+            return hit_quad(ray, geometry, tMin, tMax);
+        }
+    }
+}
+
+// Replaces a mix material at the nearest hit with one of the two materials it
+// blends, picking the second with a probability of the blend factor. Mixes
+// of mixes are resolved down to a few levels. The normal and bump maps of a
+// mix apply to materials without their own, so the Fresnel factor sees the
+// same shading normal as the material picked.
+fn resolve_mix(hitRecord: RenderState, ray: Ray, screenPos: vec2<i32>) -> RenderState {
+    var resolved = hitRecord;
+    for (var depth = 0; depth < 4 && resolved.hit && resolved.material.kind == 10u; depth++) {
+        let material = resolved.material;
+        var factor = material.mixFactor;
+        switch material.mixFactorKind {
+            case 1u: {
+                factor = evaluate_texture(material.mixTexture, resolved.uv, resolved.position, false).r;
+            }
+            case 2u: {
+                let normal = shading_normal(resolved, ray.direction);
+                let cosine = min(dot(-normalize(ray.direction), normal), 1.0);
+                factor = reflectance(cosine, material.mixFactor);
+            }
+            default {}
+        }
+
+        var index = material.mixMaterials.x;
+        if random(screenPos) < factor {
+            index = material.mixMaterials.y;
+        }
+        resolved.material = materials.materials[index];
+        resolved.materialIndex = index;
+        if resolved.material.normalTexture < 0 {
+            resolved.material.normalTexture = material.normalTexture;
+        }
+        if resolved.material.bumpTexture < 0 {
+            resolved.material.bumpTexture = material.bumpTexture;
+            resolved.material.bumpStrength = material.bumpStrength;
+        }
+    }
+    return resolved;
+}
+
+// Materials which reflect or refract without blurring, which the auxiliary
+// outputs look through.
+fn is_specular(material: Material) -> bool {
+    switch material.kind {
+        case 1u: {
+            return material.fuzz_or_refractive_index < 0.05;
+        }
+        case 2u: {
+            return true;
+        }
+        case 6u, 8u: {
+            return max(material.roughnessTangent, material.roughnessBitangent) < 0.05;
+        }
+        default {
+            return false;
+        }
+    }
+}
+
+// Decides stochastically if a hit is kept, with the probability being the
+// opacity of the material at the hit.
+fn is_opaque(hitRecord: RenderState, screenPos: vec2<i32>) -> bool {
+    let material = hitRecord.material;
+    var alpha = material.alpha;
+    if material.alphaTexture >= 0 {
+        alpha *= evaluate_texture(material.alphaTexture, hitRecord.uv, hitRecord.position, false).r;
+    }
+
+    if alpha >= 1.0 {
+        return true;
+    }
+    return random(screenPos) < alpha;
+}
+
 fn hit_sphere(ray: Ray, sphere: Geometry, tMin: f32, tMax: f32) -> RenderState {
     let co: vec3<f32> = ray.origin - sphere.center;
     let a: f32 = dot(ray.direction, ray.direction);
@@ -332,12 +1836,17 @@ fn hit_sphere(ray: Ray, sphere: Geometry, tMin: f32, tMax: f32) -> RenderState {
 			renderState.position = ray.origin + t*ray.direction;
 			renderState.normal = normalize(renderState.position - sphere.center);
             renderState.t = t;
-            renderState.material = sphere.material;
+            renderState.material = materials.materials[sphere.material];
+            renderState.materialIndex = sphere.material;
             renderState.hit = true;
             renderState.frontFace = dot(ray.direction, renderState.normal) < 0.0;
+            renderState.uv = sphere_uv(renderState.normal);
+            renderState.dpdu = sphere_dpdu(renderState.normal, sphere.radius);
+            renderState.dpdv = sphere_dpdv(renderState.normal, sphere.radius);
             if !renderState.frontFace {
                 renderState.normal = -renderState.normal;
             }
+            renderState.geometricNormal = renderState.normal;
             return renderState;
         }
     }
@@ -346,6 +1855,41 @@ fn hit_sphere(ray: Ray, sphere: Geometry, tMin: f32, tMax: f32) -> RenderState {
     return renderState;
 }
 
+// `outwardNormal` is the unit vector from the center of the sphere to the hit
+// point. u goes around the z axis and v goes from the top pole to the bottom.
+fn sphere_uv(outwardNormal: vec3<f32>) -> vec2<f32> {
+    let pi = radians(180.0);
+    let theta = acos(clamp(outwardNormal.z, -1.0, 1.0));
+    let phi = atan2(outwardNormal.y, outwardNormal.x) + pi;
+    return vec2<f32>(phi / (2.0 * pi), theta / pi);
+}
+
+// Derivative of the hit position with respect to u, which goes around the
+// z axis. The poles have no well defined direction so any tangent is used.
+fn sphere_dpdu(outwardNormal: vec3<f32>, radius: f32) -> vec3<f32> {
+    let pi = radians(180.0);
+    if length(outwardNormal.xy) < 0.0001 {
+        return vec3<f32>(2.0 * pi * radius, 0.0, 0.0);
+    }
+    return 2.0 * pi * radius * vec3<f32>(-outwardNormal.y, outwardNormal.x, 0.0);
+}
+
+// Derivative of the hit position with respect to v, which goes from the top
+// pole to the bottom one.
+fn sphere_dpdv(outwardNormal: vec3<f32>, radius: f32) -> vec3<f32> {
+    let pi = radians(180.0);
+    let sinTheta = length(outwardNormal.xy);
+    if sinTheta < 0.0001 {
+        return vec3<f32>(0.0, pi * radius, 0.0);
+    }
+    let cosTheta = outwardNormal.z;
+    return pi * radius * vec3<f32>(
+        cosTheta * outwardNormal.x / sinTheta,
+        cosTheta * outwardNormal.y / sinTheta,
+        -sinTheta
+    );
+}
+
 fn hit_quad(ray: Ray, quad: Geometry, tMin: f32, tMax: f32) -> RenderState {
     var renderState: RenderState;
     renderState.hit = false;
@@ -368,12 +1912,17 @@ fn hit_quad(ray: Ray, quad: Geometry, tMin: f32, tMax: f32) -> RenderState {
         renderState.position = ray.origin + t*ray.direction;
 		renderState.normal = n;
         renderState.t = t;
-        renderState.material = quad.material;
+        renderState.material = materials.materials[quad.material];
+        renderState.materialIndex = quad.material;
         renderState.hit = true;
         renderState.frontFace = dot(ray.direction, renderState.normal) < 0.0;
+        renderState.uv = vec2<f32>(alpha, beta);
+        renderState.dpdu = quad.u;
+        renderState.dpdv = quad.v;
         if !renderState.frontFace {
             renderState.normal = -renderState.normal;
         }
+        renderState.geometricNormal = renderState.normal;
     }
 
     return renderState;
@@ -0,0 +1,86 @@
+use wgpu::{
+    Buffer, BufferDescriptor, BufferUsages, CommandEncoder, CommandEncoderDescriptor, Device,
+    ImageCopyBuffer, ImageDataLayout, Maintain, MapMode, Queue, Texture,
+    COPY_BYTES_PER_ROW_ALIGNMENT,
+};
+
+/// Copies a texture back from the GPU, waiting for the copy to finish.
+/// Returns the tightly packed rows of `bytes_per_pixel` bytes per texel.
+pub fn read_texture(
+    device: &Device,
+    queue: &Queue,
+    texture: &Texture,
+    bytes_per_pixel: u32,
+) -> Vec<u8> {
+    let width = texture.width();
+    let height = texture.height();
+
+    // Rows of a texture copy must start at multiples of 256 bytes.
+    let unpadded_bytes_per_row = width * bytes_per_pixel;
+    let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(COPY_BYTES_PER_ROW_ALIGNMENT)
+        * COPY_BYTES_PER_ROW_ALIGNMENT;
+
+    let padded = read(
+        device,
+        queue,
+        (padded_bytes_per_row * height) as wgpu::BufferAddress,
+        |encoder, buffer| {
+            encoder.copy_texture_to_buffer(
+                texture.as_image_copy(),
+                ImageCopyBuffer {
+                    buffer,
+                    layout: ImageDataLayout {
+                        offset: 0,
+                        bytes_per_row: Some(padded_bytes_per_row),
+                        rows_per_image: Some(height),
+                    },
+                },
+                texture.size(),
+            )
+        },
+    );
+
+    padded
+        .chunks_exact(padded_bytes_per_row as usize)
+        .flat_map(|row| &row[..unpadded_bytes_per_row as usize])
+        .copied()
+        .collect()
+}
+
+/// Copies a storage buffer back from the GPU, waiting for the copy to
+/// finish. The buffer needs `BufferUsages::COPY_SRC`.
+pub fn read_buffer(device: &Device, queue: &Queue, source: &Buffer) -> Vec<u8> {
+    read(device, queue, source.size(), |encoder, buffer| {
+        encoder.copy_buffer_to_buffer(source, 0, buffer, 0, source.size())
+    })
+}
+
+/// Records `copy` into a buffer of `size` bytes which can be mapped, and
+/// returns the bytes it copied.
+fn read(
+    device: &Device,
+    queue: &Queue,
+    size: wgpu::BufferAddress,
+    copy: impl FnOnce(&mut CommandEncoder, &Buffer),
+) -> Vec<u8> {
+    let buffer = device.create_buffer(&BufferDescriptor {
+        label: Some("Readback Buffer"),
+        size,
+        usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+        label: Some("Readback Encoder"),
+    });
+    copy(&mut encoder, &buffer);
+    queue.submit([encoder.finish()]);
+
+    let slice = buffer.slice(..);
+    slice.map_async(MapMode::Read, |result| result.unwrap());
+    device.poll(Maintain::Wait);
+
+    let bytes = slice.get_mapped_range().to_vec();
+    buffer.unmap();
+    bytes
+}
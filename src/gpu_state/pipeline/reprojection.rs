@@ -0,0 +1,188 @@
+use super::camera::CameraUniform;
+use bytemuck::{Pod, Zeroable};
+use std::mem::size_of;
+use wgpu::{BindGroup, BindGroupLayout, Buffer, CommandEncoder, Device, Queue};
+
+/// Most samples a reprojected pixel keeps, so what the history got wrong
+/// fades out after a few frames.
+const MAX_HISTORY: f32 = 32.0;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct ReprojectionUniform {
+    previous_camera: CameraUniform,
+    reproject: u32,
+    max_history: f32,
+
+    _padding: [u32; 2],
+}
+
+/// Carries the samples over when the camera moves. The first hit of every
+/// path is projected into the previous frame, and the samples of the pixel
+/// it lands on are kept when that pixel saw the same object at the same
+/// depth and with the same normal.
+pub struct Reprojection {
+    enabled: bool,
+    /// Set when the camera moved since the last frame, so the next one
+    /// reprojects the history instead of adding to the accumulation.
+    pending: bool,
+    /// The camera the history was rendered with.
+    previous_camera: CameraUniform,
+    /// Copies of the accumulation and the auxiliary outputs, since pixels
+    /// read the history of others while they are written.
+    history_buffer: Buffer,
+    aov_history_buffer: Buffer,
+    uniform_buffer: Buffer,
+    bind_group_layout: BindGroupLayout,
+    bind_group: BindGroup,
+}
+
+impl Reprojection {
+    pub fn new(
+        device: &Device,
+        accumulation: &Buffer,
+        aovs: &Buffer,
+        camera: CameraUniform,
+    ) -> Reprojection {
+        let create_history_buffer = |label: &str, size: wgpu::BufferAddress| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size,
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })
+        };
+        let history_buffer = create_history_buffer("History Buffer", accumulation.size());
+        let aov_history_buffer = create_history_buffer("AOV History Buffer", aovs.size());
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Reprojection Buffer"),
+            size: size_of::<ReprojectionUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
+            mapped_at_creation: false,
+        });
+
+        let storage_entry = |binding: u32| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                storage_entry(0),
+                storage_entry(1),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("reprojection_bind_group_layout"),
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: history_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: aov_history_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("reprojection_bind_group"),
+        });
+
+        Reprojection {
+            enabled: true,
+            pending: false,
+            previous_camera: camera,
+            history_buffer,
+            aov_history_buffer,
+            uniform_buffer,
+            bind_group_layout,
+            bind_group,
+        }
+    }
+
+    pub fn bind_group_layout(&self) -> &BindGroupLayout {
+        &self.bind_group_layout
+    }
+
+    pub fn bind_group(&self) -> &BindGroup {
+        &self.bind_group
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// Makes the next frame reproject the history, as long as there is one
+    /// to reproject.
+    pub fn camera_moved(&mut self, queue: &Queue, has_samples: bool) {
+        self.pending = self.enabled && (has_samples || self.pending);
+        self.update(queue);
+    }
+
+    /// Drops a pending reprojection when the scene changed, since the
+    /// history no longer shows it.
+    pub fn discard(&mut self, queue: &Queue) {
+        self.pending = false;
+        self.update(queue);
+    }
+
+    /// Copies the accumulation and the auxiliary outputs into the history
+    /// when the frame about to be traced reprojects it.
+    pub fn copy_history(&self, encoder: &mut CommandEncoder, accumulation: &Buffer, aovs: &Buffer) {
+        if !self.pending {
+            return;
+        }
+        encoder.copy_buffer_to_buffer(
+            accumulation,
+            0,
+            &self.history_buffer,
+            0,
+            accumulation.size(),
+        );
+        encoder.copy_buffer_to_buffer(aovs, 0, &self.aov_history_buffer, 0, aovs.size());
+    }
+
+    /// Remembers the camera the frame which was just traced used.
+    pub fn finish_frame(&mut self, queue: &Queue, camera: CameraUniform) {
+        self.previous_camera = camera;
+        self.pending = false;
+        self.update(queue);
+    }
+
+    fn update(&self, queue: &Queue) {
+        let uniform = ReprojectionUniform {
+            previous_camera: self.previous_camera,
+            reproject: self.pending as u32,
+            max_history: MAX_HISTORY,
+
+            _padding: [0; 2],
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+}
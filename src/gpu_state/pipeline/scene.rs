@@ -0,0 +1,514 @@
+use super::geometry::GeometryStorage;
+use super::material::{ConductorPreset, MaterialStorage, MixFactor};
+use super::texture::TextureStorage;
+use super::volume::GridVolume;
+use bytemuck::{Pod, Zeroable};
+
+/// Everything the ray tracer needs to know about what it is rendering.
+pub struct Scene {
+    /// Names the files the render gets saved to.
+    pub name: &'static str,
+    pub materials: Vec<MaterialStorage>,
+    pub objects: Vec<GeometryStorage>,
+    /// Image textures refer to these images by their index in this list.
+    pub texture_paths: Vec<&'static str>,
+    /// Materials refer to these textures by their index in this list.
+    pub textures: Vec<TextureStorage>,
+    /// Measured materials refer to these MERL BRDF files by their index in
+    /// this list.
+    pub measured_brdf_paths: Vec<String>,
+    /// Index of a medium material filling the space outside every object,
+    /// such as fog.
+    pub medium: Option<u32>,
+    /// A box of varying density, such as smoke or fire.
+    pub volume: Option<GridVolume>,
+}
+
+impl Scene {
+    /// Names of the scenes `from_name` builds.
+    pub const NAMES: [&'static str; 4] = ["cornell_box", "textures", "materials", "media"];
+
+    /// Only the materials scene shows `brdf`, the path of a MERL BRDF file.
+    pub fn from_name(name: &str, brdf: Option<String>) -> Option<Scene> {
+        match name {
+            "cornell_box" => Some(Scene::cornell_box()),
+            "textures" => Some(Scene::textures()),
+            "materials" => Some(Scene::materials(brdf)),
+            "media" => Some(Scene::media()),
+            _ => None,
+        }
+    }
+
+    pub fn new(name: &'static str) -> Scene {
+        Scene {
+            name,
+            materials: Vec::new(),
+            objects: Vec::new(),
+            texture_paths: Vec::new(),
+            textures: Vec::new(),
+            measured_brdf_paths: Vec::new(),
+            medium: None,
+            volume: None,
+        }
+    }
+
+    /// Returns the index which objects use to refer to the material.
+    pub fn add_material(&mut self, material: MaterialStorage) -> u32 {
+        self.materials.push(material);
+        (self.materials.len() - 1) as u32
+    }
+
+    /// Returns the index which materials use to refer to the texture.
+    pub fn add_texture(&mut self, texture: TextureStorage) -> u32 {
+        self.textures.push(texture);
+        (self.textures.len() - 1) as u32
+    }
+
+    /// Returns the layer which image textures use to refer to the image.
+    pub fn add_image(&mut self, path: &'static str) -> u32 {
+        self.texture_paths.push(path);
+        (self.texture_paths.len() - 1) as u32
+    }
+
+    /// Returns the table which measured materials use to refer to the BRDF.
+    pub fn add_measured_brdf(&mut self, path: String) -> u32 {
+        self.measured_brdf_paths.push(path);
+        (self.measured_brdf_paths.len() - 1) as u32
+    }
+
+    /// Adds the light and the walls of the Cornell box.
+    fn add_room(&mut self, floor: u32, ceiling: u32, back: u32, left: u32, right: u32) {
+        let light = self.add_material(MaterialStorage::new_light([2.0, 2.0, 2.0]));
+
+        self.objects.extend([
+            GeometryStorage::new_quad([3.5, -0.5, 1.0], [0.0, 1.0, 0.0], [1.0, 0.0, 0.0], light),
+            GeometryStorage::new_quad([3.0, -1.0, -1.0], [2.0, 0.0, 0.0], [0.0, 2.0, 0.0], floor),
+            GeometryStorage::new_quad([3.0, -1.0, 1.0], [2.0, 0.0, 0.0], [0.0, 2.0, 0.0], ceiling),
+            GeometryStorage::new_quad([5.0, -1.0, -1.0], [0.0, 0.0, 2.0], [0.0, 2.0, 0.0], back),
+            GeometryStorage::new_quad([3.0, 1.0, -1.0], [2.0, 0.0, 0.0], [0.0, 0.0, 2.0], left),
+            GeometryStorage::new_quad([3.0, -1.0, -1.0], [0.0, 0.0, 2.0], [2.0, 0.0, 0.0], right),
+        ]);
+    }
+
+    /// Index of the object the shader samples as the light, which is the
+    /// first quad with a light material, or -1 when there is none. Other
+    /// lights are only found by paths which hit them.
+    pub fn light(&self) -> i32 {
+        self.objects
+            .iter()
+            .position(|object| {
+                object.is_quad() && self.materials[object.material() as usize].is_light()
+            })
+            .map_or(-1, |index| index as i32)
+    }
+
+    /// `max_density` is the highest density in the grid of the volume.
+    pub fn settings(&self, max_density: f32) -> SceneSettings {
+        let global_medium = self.medium.map_or(-1, |medium| medium as i32);
+        let light = self.light();
+
+        match &self.volume {
+            Some(volume) => SceneSettings {
+                global_medium,
+                volume_enabled: 1,
+                volume_anisotropy: volume.anisotropy,
+                volume_majorant: max_density * volume.extinction,
+                volume_min: volume.min,
+                volume_extinction: volume.extinction,
+                volume_max: volume.max,
+                volume_albedo: volume.albedo,
+                volume_emission: volume.emission,
+                light,
+
+                ..SceneSettings::zeroed()
+            },
+            None => SceneSettings {
+                global_medium,
+                light,
+                ..SceneSettings::zeroed()
+            },
+        }
+    }
+
+    pub fn cornell_box() -> Scene {
+        let mut scene = Scene::new("cornell_box");
+
+        let white_lambertian = scene.add_material(MaterialStorage::new_lambertian([1.0, 1.0, 1.0]));
+        let gray_lambertian = scene.add_material(MaterialStorage::new_lambertian([0.5, 0.5, 0.5]));
+        let red_lambertian = scene.add_material(MaterialStorage::new_lambertian([1.0, 0.0, 0.0]));
+        let green_lambertian = scene.add_material(MaterialStorage::new_lambertian([0.0, 1.0, 0.0]));
+        let white_glass = scene.add_material(MaterialStorage::new_dielectric([1.0, 1.0, 1.0], 1.5));
+        let white_light = scene.add_material(MaterialStorage::new_light([2.0, 2.0, 2.0]));
+
+        let objects = vec![
+            // Light surface
+            GeometryStorage::new_quad(
+                [3.5, -0.5, 1.0],
+                [0.0, 1.0, 0.0],
+                [1.0, 0.0, 0.0],
+                white_light,
+            ),
+            // Glass sphere
+            GeometryStorage::new_sphere([4.0, -0.5, -0.75], 0.25, white_glass),
+            GeometryStorage::new_quad(
+                [4.0, 0.25, -1.0],
+                [0.0, 0.0, 0.75],
+                [0.5, -0.25, 0.0],
+                gray_lambertian,
+            ),
+            // Grey box
+            GeometryStorage::new_quad(
+                [4.0, 0.25, -1.0],
+                [0.0, 0.0, 0.75],
+                [0.25, 0.5, 0.0],
+                gray_lambertian,
+            ),
+            GeometryStorage::new_quad(
+                [4.0, 0.25, -0.25],
+                [0.5, -0.25, 0.0],
+                [0.25, 0.5, 0.0],
+                gray_lambertian,
+            ),
+            GeometryStorage::new_quad(
+                [4.25, 0.75, -1.0],
+                [0.0, 0.0, 0.75],
+                [0.5, -0.25, 0.0],
+                gray_lambertian,
+            ),
+            GeometryStorage::new_quad(
+                [4.5, 0.0, -1.0],
+                [0.0, 0.0, 0.75],
+                [0.25, 0.5, 0.0],
+                gray_lambertian,
+            ),
+            // Enclosing multi-colored box
+            GeometryStorage::new_quad(
+                [3.0, -1.0, -1.0],
+                [2.0, 0.0, 0.0],
+                [0.0, 2.0, 0.0],
+                white_lambertian,
+            ),
+            GeometryStorage::new_quad(
+                [3.0, -1.0, 1.0],
+                [2.0, 0.0, 0.0],
+                [0.0, 2.0, 0.0],
+                white_lambertian,
+            ),
+            GeometryStorage::new_quad(
+                [5.0, -1.0, -1.0],
+                [0.0, 0.0, 2.0],
+                [0.0, 2.0, 0.0],
+                white_lambertian,
+            ),
+            GeometryStorage::new_quad(
+                [3.0, 1.0, -1.0],
+                [2.0, 0.0, 0.0],
+                [0.0, 0.0, 2.0],
+                red_lambertian,
+            ),
+            GeometryStorage::new_quad(
+                [3.0, -1.0, -1.0],
+                [0.0, 0.0, 2.0],
+                [2.0, 0.0, 0.0],
+                green_lambertian,
+            ),
+        ];
+
+        scene.objects = objects;
+        scene
+    }
+
+    /// The Cornell box hung with a picture of itself, which is also the
+    /// roughness of a metal sphere and glows on a screen, over a floor of
+    /// tilted tiles with spheres of marble, noise and checkered cubes. The
+    /// back wall is bumpy plaster, partly behind a sheer curtain, and a
+    /// grille is cut out in front of the screen.
+    pub fn textures() -> Scene {
+        let mut scene = Scene::new("textures");
+
+        let render = scene.add_image("assets/with_100_samples.png");
+        let render = scene.add_texture(TextureStorage::new_image(render));
+        let tiles = scene.add_texture(TextureStorage::new_checker_2d([0.9; 3], [0.2; 3], 8.0));
+        let cubes = scene.add_texture(TextureStorage::new_checker_3d(
+            [0.9, 0.6, 0.1],
+            [0.1, 0.2, 0.6],
+            20.0,
+        ));
+        let noise = scene.add_texture(TextureStorage::new_noise(
+            [0.1, 0.4, 0.1],
+            [0.8, 0.9, 0.6],
+            8.0,
+            4,
+        ));
+        let marble = scene.add_texture(TextureStorage::new_marble(
+            [0.9, 0.9, 0.85],
+            [0.2, 0.2, 0.25],
+            6.0,
+            5,
+        ));
+        let dusk = scene.add_texture(TextureStorage::new_gradient(
+            [0.9, 0.6, 0.4],
+            [0.4, 0.5, 0.9],
+            [1.0, 0.0],
+        ));
+        // Tangent space normals of tiles alternately tilted along u
+        let tilts = scene.add_texture(TextureStorage::new_checker_2d(
+            [0.65, 0.5, 0.85],
+            [0.35, 0.5, 0.85],
+            8.0,
+        ));
+        let plaster = scene.add_texture(TextureStorage::new_noise([0.0; 3], [1.0; 3], 30.0, 3));
+        let holes = scene.add_texture(TextureStorage::new_checker_2d([1.0; 3], [0.0; 3], 12.0));
+
+        let back = scene.add_material(
+            MaterialStorage::new_lambertian([0.8, 0.8, 0.8]).with_bump_texture(plaster, 0.005),
+        );
+        let red = scene.add_material(MaterialStorage::new_lambertian([0.8, 0.1, 0.1]));
+        let green = scene.add_material(MaterialStorage::new_lambertian([0.1, 0.8, 0.1]));
+        let floor = scene.add_material(
+            MaterialStorage::new_lambertian([1.0, 1.0, 1.0])
+                .with_albedo_texture(tiles)
+                .with_normal_texture(tilts),
+        );
+        let ceiling = scene.add_material(
+            MaterialStorage::new_lambertian([1.0, 1.0, 1.0]).with_albedo_texture(dusk),
+        );
+        scene.add_room(floor, ceiling, back, red, green);
+
+        let picture = scene.add_material(
+            MaterialStorage::new_lambertian([1.0, 1.0, 1.0]).with_albedo_texture(render),
+        );
+        let screen = scene.add_material(
+            MaterialStorage::new_light([1.5, 1.5, 1.5]).with_emission_texture(render),
+        );
+        let metal = scene.add_material(
+            MaterialStorage::new_metallic([0.9, 0.9, 0.9], 0.5).with_roughness_texture(render),
+        );
+
+        let grille = scene.add_material(
+            MaterialStorage::new_lambertian([0.1, 0.1, 0.1]).with_alpha_texture(holes),
+        );
+        let curtain =
+            scene.add_material(MaterialStorage::new_lambertian([0.9, 0.85, 0.7]).with_alpha(0.5));
+
+        let textured = [cubes, noise, marble].map(|texture| {
+            scene.add_material(
+                MaterialStorage::new_lambertian([1.0, 1.0, 1.0]).with_albedo_texture(texture),
+            )
+        });
+
+        scene.objects.extend([
+            GeometryStorage::new_sphere([3.6, 0.2, -0.85], 0.15, textured[0]),
+            GeometryStorage::new_sphere([4.5, 0.5, -0.8], 0.2, textured[1]),
+            GeometryStorage::new_sphere([3.9, 0.55, -0.75], 0.25, textured[2]),
+            // The images are upside down unless v points down.
+            GeometryStorage::new_quad(
+                [4.99, 0.5, 0.6],
+                [0.0, -1.0, 0.0],
+                [0.0, 0.0, -0.75],
+                picture,
+            ),
+            GeometryStorage::new_quad([3.4, 0.99, 0.3], [0.8, 0.0, 0.0], [0.0, 0.0, -0.6], screen),
+            GeometryStorage::new_quad([3.4, 0.97, 0.3], [0.8, 0.0, 0.0], [0.0, 0.0, -0.6], grille),
+            GeometryStorage::new_quad(
+                [4.8, -0.55, -1.0],
+                [0.0, -0.45, 0.0],
+                [0.0, 0.0, 2.0],
+                curtain,
+            ),
+            GeometryStorage::new_sphere([4.2, -0.4, -0.6], 0.4, metal),
+        ]);
+
+        scene
+    }
+
+    /// Rows of spheres on the floor of the Cornell box, one material each.
+    /// With `brdf`, the path of a MERL BRDF file, one more sphere shows it.
+    pub fn materials(brdf: Option<String>) -> Scene {
+        let mut scene = Scene::new("materials");
+
+        let white = scene.add_material(MaterialStorage::new_lambertian([0.8, 0.8, 0.8]));
+        let red = scene.add_material(MaterialStorage::new_lambertian([0.8, 0.1, 0.1]));
+        let green = scene.add_material(MaterialStorage::new_lambertian([0.1, 0.8, 0.1]));
+        scene.add_room(white, white, white, red, green);
+
+        let paint = scene.add_material(
+            MaterialStorage::new_lambertian([0.6, 0.05, 0.05]).with_clearcoat(1.0, 0.0),
+        );
+        let soap = scene.add_material(
+            MaterialStorage::new_dielectric([1.0; 3], 1.5).with_thin_film(400.0, 1.33),
+        );
+        let oil = scene.add_material(
+            MaterialStorage::new_metallic([0.3, 0.3, 0.3], 0.0).with_thin_film(300.0, 1.5),
+        );
+        let wax = scene.add_material(MaterialStorage::new_subsurface(
+            [0.95, 0.85, 0.7],
+            1.4,
+            [0.05, 0.03, 0.02],
+        ));
+        // Water filling the bottom of a glass bowl, whose wall takes
+        // precedence where they overlap.
+        let bowl =
+            scene.add_material(MaterialStorage::new_dielectric([1.0; 3], 1.5).with_priority(2));
+        let water = scene
+            .add_material(MaterialStorage::new_dielectric([0.8, 0.9, 1.0], 1.33).with_priority(1));
+        let brushed = scene.add_material(MaterialStorage::new_anisotropic_metallic(
+            [0.9, 0.9, 0.9],
+            0.05,
+            0.4,
+            0.0,
+        ));
+        let velvet = scene.add_material(MaterialStorage::new_cloth(
+            [0.25, 0.02, 0.1],
+            [1.0, 0.6, 0.8],
+            0.3,
+        ));
+        let gold = scene.add_material(MaterialStorage::new_conductor_preset(
+            ConductorPreset::Gold,
+            0.1,
+        ));
+        let copper = scene.add_material(MaterialStorage::new_conductor_preset(
+            ConductorPreset::Copper,
+            0.3,
+        ));
+        // Copper half covered in rust, gold flecked with velvet by a
+        // checker mask, and blue plastic gathering dust at grazing angles.
+        let rust = scene.add_material(MaterialStorage::new_lambertian([0.4, 0.12, 0.04]));
+        let rusty = scene.add_material(MaterialStorage::new_mix(
+            copper,
+            rust,
+            MixFactor::Constant(0.5),
+        ));
+        let mask = scene.add_texture(TextureStorage::new_checker_3d([1.0; 3], [0.0; 3], 40.0));
+        let flecked = scene.add_material(MaterialStorage::new_mix(
+            gold,
+            velvet,
+            MixFactor::Texture(mask),
+        ));
+        let plastic = scene.add_material(MaterialStorage::new_lambertian([0.05, 0.1, 0.5]));
+        let dust = scene.add_material(MaterialStorage::new_lambertian([0.8, 0.75, 0.7]));
+        let dusty = scene.add_material(MaterialStorage::new_mix(
+            plastic,
+            dust,
+            MixFactor::Fresnel(1.5),
+        ));
+
+        // The rows are staggered, so the back ones show between the front
+        // ones. Columns count from the left.
+        let sphere = |row: u32, column: u32, material: u32| {
+            let y = 0.72 - 0.32 * column as f32 - 0.16 * (row % 2) as f32;
+            GeometryStorage::new_sphere([3.4 + 0.6 * row as f32, y, -0.88], 0.12, material)
+        };
+        scene.objects.extend([
+            sphere(0, 0, paint),
+            sphere(0, 1, soap),
+            sphere(0, 2, oil),
+            sphere(0, 3, wax),
+            sphere(1, 0, bowl),
+            GeometryStorage::new_sphere([4.0, 0.56, -0.91], 0.09, water),
+            sphere(1, 1, brushed),
+            sphere(1, 2, velvet),
+            sphere(1, 3, gold),
+            sphere(2, 0, copper),
+            sphere(2, 1, rusty),
+            sphere(2, 2, flecked),
+            sphere(2, 3, dusty),
+        ]);
+
+        if let Some(path) = brdf {
+            let table = scene.add_measured_brdf(path);
+            let measured = scene.add_material(MaterialStorage::new_measured(table));
+            scene.objects.push(sphere(2, 4, measured));
+        }
+
+        scene
+    }
+
+    /// The Cornell box filled with a light fog, around a glass sphere of
+    /// murky water and a puff of smoke, whose grid is a ball of 32 cells
+    /// across with wisps of varying density.
+    pub fn media() -> Scene {
+        let mut scene = Scene::new("media");
+
+        let white = scene.add_material(MaterialStorage::new_lambertian([0.8, 0.8, 0.8]));
+        let red = scene.add_material(MaterialStorage::new_lambertian([0.8, 0.1, 0.1]));
+        let green = scene.add_material(MaterialStorage::new_lambertian([0.1, 0.8, 0.1]));
+        scene.add_room(white, white, white, red, green);
+
+        let fog = scene.add_material(MaterialStorage::new_medium([0.15; 3], [0.02; 3], 0.3));
+        scene.medium = Some(fog);
+
+        let murk = scene.add_material(MaterialStorage::new_medium(
+            [1.0, 2.0, 2.5],
+            [2.0, 0.5, 0.3],
+            0.0,
+        ));
+        let water = scene.add_material(
+            MaterialStorage::new_dielectric([1.0; 3], 1.33).with_interior_medium(murk),
+        );
+
+        scene
+            .objects
+            .push(GeometryStorage::new_sphere([4.0, -0.4, -0.65], 0.35, water));
+
+        scene.volume = Some(GridVolume {
+            path: "assets/smoke.grid",
+            min: [3.4, 0.0, -1.0],
+            max: [4.2, 0.8, -0.2],
+            albedo: [0.8, 0.8, 0.8],
+            extinction: 10.0,
+            anisotropy: 0.2,
+            emission: [0.0; 3],
+        });
+
+        scene
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct SceneSettings {
+    global_medium: i32,
+    volume_enabled: u32,
+    volume_anisotropy: f32,
+    /// Upper bound of the extinction anywhere in the volume
+    volume_majorant: f32,
+    volume_min: [f32; 3],
+    volume_extinction: f32,
+    volume_max: [f32; 3],
+    _max_padding: f32,
+    volume_albedo: [f32; 3],
+    _albedo_padding: f32,
+    volume_emission: [f32; 3],
+    light: i32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_scene_has_a_light() {
+        for name in Scene::NAMES {
+            let scene = Scene::from_name(name, None).unwrap();
+            assert!(scene.light() >= 0, "{}", name);
+        }
+    }
+
+    #[test]
+    fn finds_the_light_anywhere() {
+        let mut scene = Scene::new("light_last");
+        let white = scene.add_material(MaterialStorage::new_lambertian([1.0; 3]));
+        let light = scene.add_material(MaterialStorage::new_light([1.0; 3]));
+        scene.objects.extend([
+            GeometryStorage::new_quad([0.0; 3], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0], white),
+            // Only quads are sampled.
+            GeometryStorage::new_sphere([0.0; 3], 1.0, light),
+            GeometryStorage::new_quad([0.0; 3], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0], light),
+        ]);
+        assert_eq!(scene.light(), 2);
+
+        scene.objects.pop();
+        assert_eq!(scene.light(), -1);
+    }
+}
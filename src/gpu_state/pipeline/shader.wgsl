@@ -20,10 +20,146 @@ fn vs_main(input: VertexInput) -> VertexOutput {
 
 // Fragment shader
 
-@group(0) @binding(0) var color_buffer: texture_2d<f32>;
-@group(0) @binding(1) var screen_sampler: sampler;
+struct DisplaySettings {
+    width: u32,
+    height: u32,
+    exposure: f32,
+    toneMapping: u32,
+    encodeSrgb: u32,
+    view: u32,
+    sampleCount: u32,
+}
+
+struct Aov {
+    albedo: vec3<f32>,
+    depth: f32,
+    normal: vec3<f32>,
+    objectIndex: i32,
+    position: vec3<f32>,
+    materialIndex: i32,
+}
+
+// Radiance sums in rgb and their sample counts in w, either accumulated or
+// denoised
+@group(0) @binding(0) var<storage, read> image: array<vec4<f32>>;
+@group(0) @binding(1) var<uniform> display: DisplaySettings;
+@group(0) @binding(2) var<storage, read> aovs: array<Aov>;
+// Holds the sample counts the auxiliary outputs are summed over
+@group(0) @binding(3) var<storage, read> accumulation: array<vec4<f32>>;
 
 @fragment
 fn fs_main(@location(0) tex_coords: vec2<f32>) -> @location(0) vec4<f32> {
-    return textureSample(color_buffer, screen_sampler, tex_coords);
+    let size = vec2<u32>(display.width, display.height);
+    let pixel = min(vec2<u32>(tex_coords * vec2<f32>(size)), size - 1u);
+    let index = pixel.y * display.width + pixel.x;
+    let samples = max(accumulation[index].w, 1.0);
+    let aov = aovs[index];
+
+    var color: vec3<f32>;
+    switch display.view {
+        case 1u {
+            color = aov.albedo / samples;
+        }
+        case 2u {
+            color = aov.normal / samples * 0.5 + 0.5;
+        }
+        case 3u {
+            // Near is bright, the sky is black.
+            let depth = aov.depth / samples;
+            color = vec3<f32>(select(0.0, 1.0 / (1.0 + depth), depth > 0.0));
+        }
+        case 4u {
+            color = fract(aov.position / samples);
+        }
+        case 5u {
+            color = index_color(aov.objectIndex);
+        }
+        case 6u {
+            color = index_color(aov.materialIndex);
+        }
+        case 7u {
+            color = heat_color(accumulation[index].w / max(f32(display.sampleCount), 1.0));
+        }
+        case 0u, default {
+            let sum = image[index];
+            let radiance = sum.rgb / max(sum.w, 1.0) * exp2(display.exposure);
+            color = tone_map(radiance, display.toneMapping);
+        }
+    }
+
+    if display.encodeSrgb != 0u {
+        color = linear_to_srgb(color);
+    }
+    return vec4<f32>(color, 1.0);
+}
+
+// A distinct color for every index, and black for -1.
+fn index_color(index: i32) -> vec3<f32> {
+    if index < 0 {
+        return vec3<f32>(0.0);
+    }
+    var h = u32(index) * 747796405u + 2891336453u;
+    h = ((h >> ((h >> 28u) + 4u)) ^ h) * 277803737u;
+    h = (h >> 22u) ^ h;
+    return vec3<f32>(f32(h & 255u), f32((h >> 8u) & 255u), f32((h >> 16u) & 255u)) / 255.0;
+}
+
+// Blue for 0 through green and yellow to red for 1.
+fn heat_color(t: f32) -> vec3<f32> {
+    let x = clamp(t, 0.0, 1.0);
+    return clamp(vec3<f32>(1.5) - abs(4.0 * x - vec3<f32>(3.0, 2.0, 1.0)), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+// Maps radiance to linear colors between 0 and 1.
+fn tone_map(radiance: vec3<f32>, toneMapping: u32) -> vec3<f32> {
+    switch toneMapping {
+        case 1u {
+            return radiance / (1.0 + radiance);
+        }
+        case 2u {
+            let x = radiance;
+            return clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14), vec3<f32>(0.0), vec3<f32>(1.0));
+        }
+        case 3u {
+            return agx(radiance);
+        }
+        case 0u, default {
+            return clamp(radiance, vec3<f32>(0.0), vec3<f32>(1.0));
+        }
+    }
+}
+
+// The AgX view transform of Troy Sobotka in the minimal fit of Benjamin
+// Wrensch: a log encoding in an inset gamut, a sigmoid contrast curve and
+// back out of the inset gamut.
+fn agx(radiance: vec3<f32>) -> vec3<f32> {
+    let inset = mat3x3<f32>(
+        0.842479062253094, 0.0423282422610123, 0.0423756549057051,
+        0.0784335999999992, 0.878468636469772, 0.0784336,
+        0.0792237451477643, 0.0791661274605434, 0.879142973793104
+    );
+    let outset = mat3x3<f32>(
+        1.19687900512017, -0.0528968517574562, -0.0529716355144438,
+        -0.0980208811401368, 1.15190312990417, -0.0980434501171241,
+        -0.0990297440797205, -0.0989611768448433, 1.15107367264116
+    );
+    let minEv = -12.47393;
+    let maxEv = 4.026069;
+
+    var x = inset * max(radiance, vec3<f32>(1e-10));
+    x = (clamp(log2(x), vec3<f32>(minEv), vec3<f32>(maxEv)) - minEv) / (maxEv - minEv);
+
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    x = 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232;
+
+    // The curve produces display encoded values.
+    x = pow(max(outset * x, vec3<f32>(0.0)), vec3<f32>(2.2));
+    return clamp(x, vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+fn linear_to_srgb(color: vec3<f32>) -> vec3<f32> {
+    let low = color * 12.92;
+    let high = 1.055 * pow(color, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, low, color <= vec3<f32>(0.0031308));
 }
@@ -0,0 +1,137 @@
+use bytemuck::{Pod, Zeroable};
+use std::f32::consts::PI;
+
+/// Number of cosines of the view angle, and of roughnesses, the albedo is
+/// tabulated at. Both run from 0 to 1.
+const SHEEN_TABLE_SIZE: usize = 16;
+
+/// Directional albedo of the sheen lobe of cloth with a white sheen color,
+/// tabulated over the cosine of the view angle and the roughness. The shader
+/// dims the diffuse base of cloth by what the sheen already reflects, after
+/// Estevez and Kulla, so bright sheen over a bright base does not reflect
+/// more light than it receives. Smooth sheen seen at grazing angles reflects
+/// more than it receives on its own, which the shader scales down to 1.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct SheenAlbedo {
+    /// The albedo at roughness `i` and cosine `j` is at index
+    /// `i * SHEEN_TABLE_SIZE + j`, four to an element.
+    table: [[f32; 4]; SHEEN_TABLE_SIZE * SHEEN_TABLE_SIZE / 4],
+}
+
+impl SheenAlbedo {
+    pub fn tabulate() -> SheenAlbedo {
+        let mut albedo = SheenAlbedo::zeroed();
+        let step = 1.0 / (SHEEN_TABLE_SIZE - 1) as f32;
+        for i in 0..SHEEN_TABLE_SIZE {
+            for j in 0..SHEEN_TABLE_SIZE {
+                let index = i * SHEEN_TABLE_SIZE + j;
+                albedo.table[index / 4][index % 4] =
+                    directional_albedo(j as f32 * step, i as f32 * step, 64);
+            }
+        }
+        albedo
+    }
+}
+
+/// Integrates the sheen lobe times the cosine of the light over the
+/// hemisphere in `steps` elevations and twice as many azimuths.
+fn directional_albedo(cos_view: f32, roughness: f32, steps: usize) -> f32 {
+    integrate_hemisphere(cos_view, steps, |cos_light, cos_half| {
+        sheen(cos_light, cos_view, cos_half, roughness)
+    })
+}
+
+/// Integrates `brdf` times the cosine of the light over the hemisphere, as
+/// seen from a view direction at `cos_view`. `brdf` takes the cosines of the
+/// light and the half vector.
+fn integrate_hemisphere(cos_view: f32, steps: usize, brdf: impl Fn(f32, f32) -> f32) -> f32 {
+    let view = [(1.0 - cos_view * cos_view).max(0.0).sqrt(), 0.0, cos_view];
+    let d_theta = 0.5 * PI / steps as f32;
+    let d_phi = PI / steps as f32;
+
+    let mut sum = 0.0;
+    for i in 0..steps {
+        let theta = (i as f32 + 0.5) * d_theta;
+        let (sin_theta, cos_light) = theta.sin_cos();
+        for j in 0..2 * steps {
+            let phi = (j as f32 + 0.5) * d_phi;
+            let light = [sin_theta * phi.cos(), sin_theta * phi.sin(), cos_light];
+            let half = [view[0] + light[0], view[1] + light[1], view[2] + light[2]];
+            let length = (half[0] * half[0] + half[1] * half[1] + half[2] * half[2]).sqrt();
+            if length == 0.0 {
+                continue;
+            }
+            sum += brdf(cos_light, half[2] / length) * cos_light * sin_theta * d_theta * d_phi;
+        }
+    }
+    sum
+}
+
+/// The sheen lobe of the shader: the Charlie distribution with the
+/// visibility term of Neubelt and Pettineo.
+fn sheen(cos_light: f32, cos_view: f32, cos_half: f32, roughness: f32) -> f32 {
+    let alpha = roughness.max(0.01);
+    let sin_half = (1.0 - cos_half * cos_half).max(0.0).sqrt();
+    let distribution = (2.0 + 1.0 / alpha) * sin_half.powf(1.0 / alpha) / (2.0 * PI);
+    let visibility = 1.0 / (4.0 * (cos_light + cos_view - cos_light * cos_view));
+    distribution * visibility
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Interpolates the table the way the shader does.
+    fn lookup(albedo: &SheenAlbedo, cos_view: f32, roughness: f32) -> f32 {
+        let last = (SHEEN_TABLE_SIZE - 1) as f32;
+        let x = cos_view.clamp(0.0, 1.0) * last;
+        let y = roughness.clamp(0.0, 1.0) * last;
+        let (x0, y0) = (x.floor().min(last - 1.0), y.floor().min(last - 1.0));
+        let at = |i: f32, j: f32| {
+            let index = i as usize * SHEEN_TABLE_SIZE + j as usize;
+            albedo.table[index / 4][index % 4]
+        };
+        let (fx, fy) = (x - x0, y - y0);
+        let low = at(y0, x0) * (1.0 - fx) + at(y0, x0 + 1.0) * fx;
+        let high = at(y0 + 1.0, x0) * (1.0 - fx) + at(y0 + 1.0, x0 + 1.0) * fx;
+        low * (1.0 - fy) + high * fy
+    }
+
+    #[test]
+    fn sheen_brightens_grazing_angles() {
+        let albedo = SheenAlbedo::tabulate();
+        for roughness in [0.0, 0.4, 1.0] {
+            assert!(lookup(&albedo, 0.0, roughness) > lookup(&albedo, 1.0, roughness));
+        }
+    }
+
+    /// White cloth under uniform white light reflects all of it, and
+    /// without dimming the base it would reflect more where the sheen shows.
+    #[test]
+    fn white_cloth_passes_the_white_furnace() {
+        let albedo = SheenAlbedo::tabulate();
+        let mut uncompensated: f32 = 0.0;
+        for cos_view in [0.1, 0.37, 0.5, 0.83, 1.0] {
+            for roughness in [0.05, 0.3, 0.55, 0.9] {
+                let sheen_albedo = lookup(&albedo, cos_view, roughness);
+                let reflected = |base: f32, sheen_scale: f32| {
+                    integrate_hemisphere(cos_view, 96, |cos_light, cos_half| {
+                        base / PI + sheen_scale * sheen(cos_light, cos_view, cos_half, roughness)
+                    })
+                };
+                let compensated =
+                    reflected(1.0 - sheen_albedo.min(1.0), 1.0 / sheen_albedo.max(1.0));
+                let label = format!("cosine {} and roughness {}", cos_view, roughness);
+                assert!(
+                    (compensated - 1.0).abs() < 0.02,
+                    "{}: {}",
+                    label,
+                    compensated
+                );
+                uncompensated = uncompensated.max(reflected(1.0, 1.0));
+            }
+        }
+        assert!(uncompensated > 1.2, "{}", uncompensated);
+    }
+}
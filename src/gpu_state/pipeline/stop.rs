@@ -0,0 +1,118 @@
+use std::time::Duration;
+
+/// When a render is finished, which is as soon as any of the criteria is
+/// met. Without criteria it never is.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct StopCriteria {
+    /// Frames traced since the samples were reset. Every frame adds a sample
+    /// to each pixel, except the pixels adaptive sampling stopped tracing,
+    /// which end up with fewer.
+    samples: Option<u32>,
+    time: Option<Duration>,
+    /// Mean over the pixels of the relative standard error of their mean
+    /// luminance.
+    error: Option<f32>,
+}
+
+impl StopCriteria {
+    pub fn with_samples(mut self, samples: u32) -> StopCriteria {
+        self.samples = Some(samples);
+        self
+    }
+
+    pub fn with_time(mut self, time: Duration) -> StopCriteria {
+        self.time = Some(time);
+        self
+    }
+
+    pub fn with_error(mut self, error: f32) -> StopCriteria {
+        self.error = Some(error);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_none() && self.time.is_none() && self.error.is_none()
+    }
+
+    pub fn samples(&self) -> Option<u32> {
+        self.samples
+    }
+
+    pub fn time(&self) -> Option<Duration> {
+        self.time
+    }
+
+    pub fn error(&self) -> Option<f32> {
+        self.error
+    }
+}
+
+/// Mean of the relative standard error of the mean luminance of the pixels
+/// with at least two samples, estimated the same way adaptive sampling
+/// does. `moments` holds the sums of the luminance, before the weight of the
+/// filter, of its square and the sample count of every pixel.
+pub fn mean_relative_error(moments: &[[f32; 4]]) -> f32 {
+    let errors: Vec<f32> = moments
+        .iter()
+        .filter(|moment| moment[2] >= 2.0)
+        .map(|&[sum, square_sum, samples, _]| {
+            let mean = sum / samples;
+            let variance =
+                (square_sum / samples - mean * mean).max(0.0) * samples / (samples - 1.0);
+            (variance / samples).sqrt() / mean.max(1e-3)
+        })
+        .collect();
+
+    if errors.is_empty() {
+        f32::INFINITY
+    } else {
+        errors.iter().sum::<f32>() / errors.len() as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Moments of a pixel whose samples are the given luminances.
+    fn moments(luminances: &[f32]) -> [f32; 4] {
+        [
+            luminances.iter().sum(),
+            luminances.iter().map(|l| l * l).sum(),
+            luminances.len() as f32,
+            0.0,
+        ]
+    }
+
+    #[test]
+    fn error_without_pixels_is_infinite() {
+        assert_eq!(mean_relative_error(&[]), f32::INFINITY);
+    }
+
+    #[test]
+    fn error_ignores_pixels_with_fewer_than_two_samples() {
+        assert_eq!(
+            mean_relative_error(&[moments(&[]), moments(&[1.0])]),
+            f32::INFINITY
+        );
+
+        let error = mean_relative_error(&[moments(&[5.0]), moments(&[1.0, 3.0])]);
+        // Standard error of 1 around a mean of 2
+        assert!((error - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn error_of_constant_pixels_is_zero() {
+        assert_eq!(
+            mean_relative_error(&[moments(&[0.5; 4]), moments(&[2.0; 8])]),
+            0.0
+        );
+    }
+
+    #[test]
+    fn error_stays_finite_near_black() {
+        let error = mean_relative_error(&[moments(&[0.0, 1e-6, 0.0, 0.0])]);
+        assert!(error.is_finite());
+        assert!(error < 1e-3);
+    }
+}
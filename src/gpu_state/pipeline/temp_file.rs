@@ -0,0 +1,27 @@
+use std::fs;
+use std::path::PathBuf;
+
+/// A file of its own in the temporary directory, for tests of the loaders.
+/// It is removed when dropped.
+pub struct TempFile {
+    path: PathBuf,
+}
+
+impl TempFile {
+    /// `name` has to be unique among the tests, which run in parallel.
+    pub fn new(name: &str, bytes: &[u8]) -> TempFile {
+        let path = std::env::temp_dir().join(format!("{}_{}", name, std::process::id()));
+        fs::write(&path, bytes).unwrap();
+        TempFile { path }
+    }
+
+    pub fn path(&self) -> &str {
+        self.path.to_str().unwrap()
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
@@ -0,0 +1,233 @@
+use bytemuck::{Pod, Zeroable};
+use image::imageops::FilterType;
+use wgpu::{
+    AddressMode, Device, Extent3d, FilterMode, ImageCopyTexture, ImageDataLayout, Origin3d, Queue,
+    Sampler, SamplerDescriptor, TextureAspect, TextureDescriptor, TextureDimension, TextureFormat,
+    TextureUsages, TextureView, TextureViewDescriptor, TextureViewDimension,
+};
+
+/// Every image is resized to this resolution so that all of them fit in a
+/// single texture array.
+pub const TEXTURE_SIZE: u32 = 1024;
+
+/// All the image textures of a scene, stored as the layers of one texture
+/// array. An image `TextureStorage` refers to an image by its layer index.
+pub struct TextureArray {
+    view: TextureView,
+    sampler: Sampler,
+}
+
+impl TextureArray {
+    /// Loads every PNG or JPEG file in `paths` in order, so the texture at
+    /// `paths[i]` gets the layer index `i`.
+    pub fn new(device: &Device, queue: &Queue, paths: &[&str]) -> TextureArray {
+        // A texture array cannot be empty, so a single white texel stands
+        // in when the scene has no textures. The GL backend makes a plain 2D
+        // texture out of a single layer, which reads as black through an
+        // array view, so there are always at least two layers.
+        let (side, layers) = if paths.is_empty() {
+            (1, 2)
+        } else {
+            (TEXTURE_SIZE, (paths.len() as u32).max(2))
+        };
+
+        let layer_size = Extent3d {
+            width: side,
+            height: side,
+            depth_or_array_layers: 1,
+        };
+
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some("Texture Array"),
+            size: Extent3d {
+                depth_or_array_layers: layers,
+                ..layer_size
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Rgba8Unorm,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        let white = [255u8; 4].repeat((side * side) as usize);
+        for layer in 0..layers {
+            let pixels = match paths.get(layer as usize) {
+                Some(path) => load_image(path),
+                None => white.clone(),
+            };
+
+            queue.write_texture(
+                ImageCopyTexture {
+                    texture: &texture,
+                    mip_level: 0,
+                    origin: Origin3d {
+                        x: 0,
+                        y: 0,
+                        z: layer,
+                    },
+                    aspect: TextureAspect::All,
+                },
+                &pixels,
+                ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(side * 4),
+                    rows_per_image: Some(side),
+                },
+                layer_size,
+            );
+        }
+
+        // A single layer texture would otherwise get a plain 2D view.
+        let view = texture.create_view(&TextureViewDescriptor {
+            dimension: Some(TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            address_mode_u: AddressMode::Repeat,
+            address_mode_v: AddressMode::Repeat,
+            address_mode_w: AddressMode::Repeat,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            mipmap_filter: FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        TextureArray { view, sampler }
+    }
+
+    pub fn view(&self) -> &TextureView {
+        &self.view
+    }
+
+    pub fn sampler(&self) -> &Sampler {
+        &self.sampler
+    }
+}
+
+/// Returns the RGBA8 pixels of the image at `path` resized to `TEXTURE_SIZE`.
+fn load_image(path: &str) -> Vec<u8> {
+    let image = image::open(path)
+        .unwrap_or_else(|e| panic!("Failed to load texture {}: {}", path, e))
+        .resize_exact(TEXTURE_SIZE, TEXTURE_SIZE, FilterType::Triangle);
+
+    image.to_rgba8().into_raw()
+}
+
+pub enum TextureKind {
+    /// `layer` is the index of the image in the scene's `TextureArray`.
+    Image { layer: u32 },
+    /// Squares alternating between the two colors over the uv coordinates.
+    /// `scale` is the number of squares along each side of the surface.
+    Checker2d { scale: f32 },
+    /// Cubes alternating between the two colors in world space. `scale` is
+    /// the number of cubes per unit length.
+    Checker3d { scale: f32 },
+    /// Fractal Perlin noise blending the two colors in world space.
+    Noise { scale: f32, octaves: u32 },
+    /// Veins of the second color in the first, made by perturbing a sine
+    /// wave along z with turbulence.
+    Marble { scale: f32, octaves: u32 },
+    /// Linear blend from the first color to the second over the uv
+    /// coordinates. The blend factor is `dot(uv, direction)`.
+    Gradient { direction: [f32; 2] },
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct TextureStorage {
+    color_a: [f32; 3],
+    kind: u32,
+    color_b: [f32; 3],
+    scale: f32,
+    direction: [f32; 2],
+    layer_or_octaves: u32,
+    _padding: f32,
+}
+
+impl TextureStorage {
+    fn new_with_padding(
+        color_a: [f32; 3],
+        color_b: [f32; 3],
+        kind: u32,
+        scale: f32,
+        direction: [f32; 2],
+        layer_or_octaves: u32,
+    ) -> TextureStorage {
+        TextureStorage {
+            color_a,
+            kind,
+            color_b,
+            scale,
+            direction,
+            layer_or_octaves,
+
+            _padding: 0.0,
+        }
+    }
+
+    /// Procedural textures blend between `color_a` and `color_b`. Scalar
+    /// material parameters use the red channel of the result.
+    pub fn new(color_a: [f32; 3], color_b: [f32; 3], kind: TextureKind) -> TextureStorage {
+        match kind {
+            TextureKind::Image { layer } => {
+                TextureStorage::new_with_padding(color_a, color_b, 0, 0.0, [0.0; 2], layer)
+            }
+            TextureKind::Checker2d { scale } => {
+                TextureStorage::new_with_padding(color_a, color_b, 1, scale, [0.0; 2], 0)
+            }
+            TextureKind::Checker3d { scale } => {
+                TextureStorage::new_with_padding(color_a, color_b, 2, scale, [0.0; 2], 0)
+            }
+            TextureKind::Noise { scale, octaves } => {
+                TextureStorage::new_with_padding(color_a, color_b, 3, scale, [0.0; 2], octaves)
+            }
+            TextureKind::Marble { scale, octaves } => {
+                TextureStorage::new_with_padding(color_a, color_b, 4, scale, [0.0; 2], octaves)
+            }
+            TextureKind::Gradient { direction } => {
+                TextureStorage::new_with_padding(color_a, color_b, 5, 0.0, direction, 0)
+            }
+        }
+    }
+
+    pub fn new_image(layer: u32) -> TextureStorage {
+        TextureStorage::new([1.0; 3], [1.0; 3], TextureKind::Image { layer })
+    }
+
+    pub fn new_checker_2d(color_a: [f32; 3], color_b: [f32; 3], scale: f32) -> TextureStorage {
+        TextureStorage::new(color_a, color_b, TextureKind::Checker2d { scale })
+    }
+
+    pub fn new_checker_3d(color_a: [f32; 3], color_b: [f32; 3], scale: f32) -> TextureStorage {
+        TextureStorage::new(color_a, color_b, TextureKind::Checker3d { scale })
+    }
+
+    pub fn new_noise(
+        color_a: [f32; 3],
+        color_b: [f32; 3],
+        scale: f32,
+        octaves: u32,
+    ) -> TextureStorage {
+        TextureStorage::new(color_a, color_b, TextureKind::Noise { scale, octaves })
+    }
+
+    pub fn new_marble(
+        color_a: [f32; 3],
+        color_b: [f32; 3],
+        scale: f32,
+        octaves: u32,
+    ) -> TextureStorage {
+        TextureStorage::new(color_a, color_b, TextureKind::Marble { scale, octaves })
+    }
+
+    pub fn new_gradient(
+        color_a: [f32; 3],
+        color_b: [f32; 3],
+        direction: [f32; 2],
+    ) -> TextureStorage {
+        TextureStorage::new(color_a, color_b, TextureKind::Gradient { direction })
+    }
+}
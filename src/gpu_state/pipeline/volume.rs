@@ -0,0 +1,196 @@
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use wgpu::{
+    Device, Extent3d, Queue, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages,
+    TextureView, TextureViewDescriptor,
+};
+
+/// Magic bytes at the start of a density grid file.
+const GRID_MAGIC: &[u8; 4] = b"GRID";
+
+/// A box of smoke, cloud or fire whose density varies over a grid.
+pub struct GridVolume {
+    /// Path of the density grid file, see `DensityGrid::load`.
+    pub path: &'static str,
+    /// Opposite corners of the axis-aligned box the grid is stretched over.
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+    /// Fraction of the extinction which is scattering rather than absorption.
+    pub albedo: [f32; 3],
+    /// Extinction coefficient per unit length at a density of 1.
+    pub extinction: f32,
+    /// Henyey-Greenstein asymmetry of the scattering.
+    pub anisotropy: f32,
+    /// Radiance emitted by the absorbing part of the volume at a density of
+    /// 1, for fire.
+    pub emission: [f32; 3],
+}
+
+/// A dense grid of densities.
+pub struct DensityGrid {
+    size: [u32; 3],
+    densities: Vec<f32>,
+}
+
+impl DensityGrid {
+    /// Loads a density grid file, laid out as
+    ///
+    /// * the 4 magic bytes `GRID`,
+    /// * the number of cells along x, y and z as little endian `u32`s,
+    /// * the density of every cell as a little endian `f32`, with x varying
+    ///   fastest and z slowest.
+    pub fn load(path: &str) -> Result<DensityGrid> {
+        let bytes = fs::read(path)?;
+        if bytes.len() < 16 || &bytes[0..4] != GRID_MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "not a density grid"));
+        }
+
+        let read_u32 = |offset: usize| {
+            u32::from_le_bytes([
+                bytes[offset],
+                bytes[offset + 1],
+                bytes[offset + 2],
+                bytes[offset + 3],
+            ])
+        };
+        let size = [read_u32(4), read_u32(8), read_u32(12)];
+
+        // A corrupt size could overflow the number of bytes it needs.
+        let length = size
+            .iter()
+            .try_fold(4usize, |length, &n| length.checked_mul(n as usize))
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "density grid is too large"))?;
+        let data = &bytes[16..];
+        if length == 0 || data.len() != length {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "density grid size does not match its data",
+            ));
+        }
+
+        let densities = data
+            .chunks_exact(4)
+            .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+            .collect();
+
+        Ok(DensityGrid { size, densities })
+    }
+
+    pub fn max_density(&self) -> f32 {
+        self.densities.iter().copied().fold(0.0, f32::max)
+    }
+
+    /// Uploads the grid into a 3D texture. Without a grid a single empty cell
+    /// is uploaded, since the shader always needs a texture bound. Fails when
+    /// the grid has more cells along an axis than the device supports.
+    pub fn create_view(
+        grid: Option<&DensityGrid>,
+        device: &Device,
+        queue: &Queue,
+    ) -> Result<TextureView> {
+        let (size, densities) = match grid {
+            Some(grid) => (grid.size, &grid.densities[..]),
+            None => ([1, 1, 1], &[0.0][..]),
+        };
+
+        let max_size = device.limits().max_texture_dimension_3d;
+        if size.iter().any(|&n| n > max_size) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "density grid of {}x{}x{} cells exceeds the limit of {} per axis",
+                    size[0], size[1], size[2], max_size
+                ),
+            ));
+        }
+
+        let extent = Extent3d {
+            width: size[0],
+            height: size[1],
+            depth_or_array_layers: size[2],
+        };
+
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some("Density Grid"),
+            size: extent,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D3,
+            format: TextureFormat::R32Float,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        queue.write_texture(
+            texture.as_image_copy(),
+            bytemuck::cast_slice(densities),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(size[0] * 4),
+                rows_per_image: Some(size[1]),
+            },
+            extent,
+        );
+
+        Ok(texture.create_view(&TextureViewDescriptor::default()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpu_state::pipeline::temp_file::TempFile;
+
+    fn grid_bytes(size: [u32; 3], densities: &[f32]) -> Vec<u8> {
+        let mut bytes = GRID_MAGIC.to_vec();
+        bytes.extend(size.iter().flat_map(|n| n.to_le_bytes()));
+        bytes.extend(densities.iter().flat_map(|d| d.to_le_bytes()));
+        bytes
+    }
+
+    #[test]
+    fn loads_a_grid() {
+        let densities = [0.0, 0.5, 1.0, 0.25, 2.0, 0.0];
+        let file = TempFile::new("loads_a_grid", &grid_bytes([3, 2, 1], &densities));
+
+        let grid = DensityGrid::load(file.path()).unwrap();
+        assert_eq!(grid.size, [3, 2, 1]);
+        assert_eq!(grid.densities, densities);
+        assert_eq!(grid.max_density(), 2.0);
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let mut bytes = grid_bytes([1, 1, 1], &[1.0]);
+        bytes[0..4].copy_from_slice(b"GIRD");
+        let file = TempFile::new("rejects_other_files", &bytes);
+
+        let error = DensityGrid::load(file.path()).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_a_size_which_does_not_match_the_data() {
+        for (name, size) in [("too_few_cells", [2, 2, 2]), ("no_cells", [0, 1, 1])] {
+            let file = TempFile::new(name, &grid_bytes(size, &[1.0]));
+
+            let error = DensityGrid::load(file.path()).err().unwrap();
+            assert_eq!(error.kind(), ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn rejects_a_size_which_overflows() {
+        let file = TempFile::new("overflowing_size", &grid_bytes([u32::MAX; 3], &[1.0]));
+
+        let error = DensityGrid::load(file.path()).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn loads_the_smoke_asset() {
+        let grid = DensityGrid::load("assets/smoke.grid").unwrap();
+        assert_eq!(grid.size, [32, 32, 32]);
+        assert!(grid.max_density() > 0.0);
+    }
+}
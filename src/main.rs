@@ -1,45 +1,63 @@
 mod gpu_state;
 
-use gpu_state::GpuState;
+use gpu_state::{
+    ExrPrecision, FilterKind, FinishedOutput, GpuState, Headless, PixelFilter, Scene, StopCriteria,
+};
+use std::time::Duration;
 use winit::{
+    dpi::PhysicalSize,
     event::*,
     event_loop::{ControlFlow, EventLoop},
     window::WindowBuilder,
 };
 
-pub async fn run() {
+pub async fn run(options: Options) {
     env_logger::init();
+    let scene = options.scene();
     let event_loop = EventLoop::new();
     let window = WindowBuilder::new().build(&event_loop).unwrap();
 
-    let mut state = GpuState::new(window).await;
+    let mut state = GpuState::new(window, scene).await;
+    state.set_denoiser_enabled(options.denoise);
+    if options.adaptive {
+        state.set_adaptive_sampling(true);
+    }
+    if let Some(threshold) = options.noise_threshold {
+        state.set_noise_threshold(threshold);
+    }
+    if let Some(filter) = options.pixel_filter() {
+        state.set_filter(filter);
+    }
+    state.set_stop_criteria(options.stop_criteria);
+    state.set_exr_precision(options.precision);
+    if options.save || options.output.is_some() {
+        state.set_finished_output(Some(FinishedOutput::new(options.output, options.precision)));
+    }
 
     event_loop.run(move |event, _, control_flow| match event {
         Event::WindowEvent {
             ref event,
             window_id,
-        } if window_id == state.window().id() => {
-            if !state.input(event) {
-                match event {
-                    WindowEvent::CloseRequested
-                    | WindowEvent::KeyboardInput {
-                        input:
-                            KeyboardInput {
-                                state: ElementState::Pressed,
-                                virtual_keycode: Some(VirtualKeyCode::Escape),
-                                ..
-                            },
-                        ..
-                    } => *control_flow = ControlFlow::Exit,
-                    WindowEvent::Resized(physical_size) => {
-                        state.resize(*physical_size);
-                    }
-                    WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
-                        // new_inner_size is &&mut so we have to dereference it twice
-                        state.resize(**new_inner_size);
-                    }
-                    _ => {}
+        } if window_id == state.window().id() && !state.input(event) => {
+            match event {
+                WindowEvent::CloseRequested
+                | WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode: Some(VirtualKeyCode::Escape),
+                            ..
+                        },
+                    ..
+                } => *control_flow = ControlFlow::Exit,
+                WindowEvent::Resized(physical_size) => {
+                    state.resize(*physical_size);
+                }
+                WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                    // new_inner_size is &&mut so we have to dereference it twice
+                    state.resize(**new_inner_size);
                 }
+                _ => {}
             }
         }
         Event::RedrawRequested(window_id) if window_id == state.window().id() => {
@@ -56,13 +74,189 @@ pub async fn run() {
         }
         Event::MainEventsCleared => {
             // RedrawRequested will only trigger once, unless we manually
-            // request it.
-            state.window().request_redraw();
+            // request it. A finished render waits for input instead.
+            if state.finished() {
+                *control_flow = ControlFlow::Wait;
+            } else {
+                *control_flow = ControlFlow::Poll;
+                state.window().request_redraw();
+            }
         }
         _ => {}
     });
 }
 
+/// How the options are given, printed when they cannot be parsed.
+const USAGE: &str = "Usage: path_tracing [--headless] [--scene NAME] [--brdf PATH] [--samples N]
+    [--time SECONDS] [--error E] [--width W] [--height H] [--output PATH] [--save]
+    [--half | --float] [--denoise] [--adaptive] [--noise-threshold T] [--filter NAME]
+    [--filter-radius R]";
+
+/// Options given as in `USAGE`. The size only applies without a window.
+pub struct Options {
+    headless: bool,
+    scene: String,
+    /// A MERL BRDF file which the materials scene shows.
+    brdf: Option<String>,
+    stop_criteria: StopCriteria,
+    size: PhysicalSize<u32>,
+    output: Option<String>,
+    /// Whether the window saves the render once it is finished.
+    save: bool,
+    precision: ExrPrecision,
+    denoise: bool,
+    adaptive: bool,
+    noise_threshold: Option<f32>,
+    filter: Option<FilterKind>,
+    filter_radius: Option<f32>,
+}
+
+impl Options {
+    fn from_args(args: impl Iterator<Item = String>) -> Options {
+        let mut args = args.peekable();
+
+        let mut options = Options {
+            headless: false,
+            scene: "cornell_box".to_string(),
+            brdf: None,
+            stop_criteria: StopCriteria::default(),
+            size: PhysicalSize::new(800, 600),
+            output: None,
+            save: false,
+            precision: ExrPrecision::Float,
+            denoise: false,
+            adaptive: false,
+            noise_threshold: None,
+            filter: None,
+            filter_radius: None,
+        };
+
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                args.next()
+                    .unwrap_or_else(|| usage_error(&format!("{} needs a value", name)))
+            };
+            fn number<T: std::str::FromStr>(name: &str, value: String) -> T {
+                value.parse().unwrap_or_else(|_| {
+                    usage_error(&format!("{} needs a number, not {}", name, value))
+                })
+            }
+            fn positive(name: &str, value: String) -> f32 {
+                let number: f32 = number(name, value);
+                if !(number.is_finite() && number > 0.0) {
+                    usage_error(&format!("{} needs a positive number, not {}", name, number));
+                }
+                number
+            }
+
+            let criteria = options.stop_criteria;
+            match arg.as_str() {
+                "--headless" => options.headless = true,
+                "--scene" => options.scene = value(&arg),
+                "--brdf" => options.brdf = Some(value(&arg)),
+                "--samples" => {
+                    options.stop_criteria = criteria.with_samples(number(&arg, value(&arg)))
+                }
+                "--time" => {
+                    let seconds = positive(&arg, value(&arg));
+                    let time = Duration::try_from_secs_f32(seconds).unwrap_or_else(|_| {
+                        usage_error(&format!("{} needs fewer seconds than {}", arg, seconds))
+                    });
+                    options.stop_criteria = criteria.with_time(time);
+                }
+                "--error" => {
+                    options.stop_criteria = criteria.with_error(positive(&arg, value(&arg)))
+                }
+                "--width" => options.size.width = number(&arg, value(&arg)),
+                "--height" => options.size.height = number(&arg, value(&arg)),
+                "--output" => options.output = Some(value(&arg)),
+                "--save" => options.save = true,
+                "--half" => options.precision = ExrPrecision::Half,
+                "--float" => options.precision = ExrPrecision::Float,
+                "--denoise" => options.denoise = true,
+                "--adaptive" => options.adaptive = true,
+                "--noise-threshold" => {
+                    options.noise_threshold = Some(number(&arg, value(&arg)));
+                }
+                "--filter" => {
+                    let name = value(&arg);
+                    options.filter = Some(FilterKind::from_name(&name).unwrap_or_else(|| {
+                        usage_error(&format!(
+                            "{} needs box, tent, gaussian, blackman-harris or mitchell, not {}",
+                            arg, name
+                        ))
+                    }));
+                }
+                "--filter-radius" => options.filter_radius = Some(positive(&arg, value(&arg))),
+                _ => usage_error(&format!("Unknown option {}", arg)),
+            }
+        }
+
+        // Without a window there is no one to stop the render.
+        if options.headless && options.stop_criteria.is_empty() {
+            options.stop_criteria = options.stop_criteria.with_samples(100);
+        }
+
+        options
+    }
+
+    fn scene(&self) -> Scene {
+        Scene::from_name(&self.scene, self.brdf.clone()).unwrap_or_else(|| {
+            usage_error(&format!(
+                "--scene needs one of {}, not {}",
+                Scene::NAMES.join(", "),
+                self.scene
+            ))
+        })
+    }
+
+    /// The filter asked for, at its usual radius without one.
+    fn pixel_filter(&self) -> Option<PixelFilter> {
+        if self.filter.is_none() && self.filter_radius.is_none() {
+            return None;
+        }
+        let filter = self
+            .filter
+            .map_or_else(PixelFilter::default, PixelFilter::new);
+        Some(match self.filter_radius {
+            Some(radius) => filter.with_radius(radius),
+            None => filter,
+        })
+    }
+}
+
+async fn run_headless(options: Options) {
+    env_logger::init();
+    let mut headless = Headless::new(options.size, options.scene()).await;
+    headless.set_denoiser_enabled(options.denoise);
+    if options.adaptive {
+        headless.set_adaptive_sampling(true);
+    }
+    if let Some(threshold) = options.noise_threshold {
+        headless.set_noise_threshold(threshold);
+    }
+    if let Some(filter) = options.pixel_filter() {
+        headless.set_filter(filter);
+    }
+    headless.render(&options.stop_criteria);
+
+    match headless.save_exr(options.output.as_deref(), options.precision) {
+        Ok(path) => println!("Saved {}", path),
+        Err(e) => eprintln!("Failed to save OpenEXR file: {}", e),
+    }
+}
+
+/// Exits after printing what is wrong with the options and how to give them.
+fn usage_error(message: &str) -> ! {
+    eprintln!("{}\n\n{}", message, USAGE);
+    std::process::exit(2);
+}
+
 fn main() {
-    pollster::block_on(run());
+    let options = Options::from_args(std::env::args().skip(1));
+    if options.headless {
+        pollster::block_on(run_headless(options));
+    } else {
+        pollster::block_on(run(options));
+    }
 }